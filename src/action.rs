@@ -0,0 +1,637 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combat::{enter_tile, ActorEffect, AttackDice, HitPoints, TargetEvent},
+    dungeon::ChangeLevelEvent,
+    equipment::{Equipment, Equippable},
+    inventory::{Consumable, Inventory, Item, ItemEffect},
+    map::{Map, MapTile},
+    map_state::{MapActors, MapObstacles},
+    monster::Monster,
+    movement::{Movement, Position},
+    player::Player,
+    rng::DiceRng,
+    targeting::{in_range, line_of_fire, visible_targets},
+    turn_system::{
+        Actor, Energy, TakingATurn, ATTACK_COST, DROP_COST, EQUIP_COST, MOVE_COST, OPEN_DOOR_COST,
+        PICK_UP_COST, STAIRS_COST, TURN_ENERGY, USE_ITEM_COST, WAIT_COST,
+    },
+    ui::PrintLog,
+    visibility::MapView,
+};
+
+/// Label for the system applying actions. Occurs in [CoreStage::Update].
+pub const RESOLVE_ACTIONS_SYSTEM_LABEL: &str = "resolve_actions";
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ActionEvent>()
+        .add_event::<ActionOutcome>()
+        .add_system(resolve_actions.label(RESOLVE_ACTIONS_SYSTEM_LABEL));
+    }
+}
+
+/// Something an actor does on its turn.
+///
+/// Player input and monster AI both produce actions, which are checked and
+/// applied by the same rules in [resolve_actions]. The player's actions are
+/// what gets recorded in replays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    /// Move in the given direction.
+    Move([i32;2]),
+    /// Attack whatever is in the given direction.
+    Attack([i32;2]),
+    /// Open the door in the given direction.
+    OpenDoor([i32;2]),
+    /// Do nothing and end the turn.
+    Wait,
+    /// Take the stairs down.
+    Descend,
+    /// Take the stairs up.
+    Ascend,
+    /// Pick up the item the actor is standing on.
+    PickUp,
+    /// Drop the item in the given inventory slot.
+    Drop(usize),
+    /// Use the item in the given inventory slot.
+    UseItem(usize),
+    /// Use the item in the given inventory slot on the given position.
+    UseItemAt(usize, [i32;2]),
+    /// Equip or unequip the item in the given inventory slot.
+    Equip(usize),
+}
+
+impl Action {
+    /// The energy the action costs. Moving costs the
+    /// [crate::map::TileProperties::move_cost] of the tile moved onto instead.
+    pub fn cost(&self) -> i32 {
+        match self {
+            Action::Move(_) => MOVE_COST,
+            Action::Attack(_) => ATTACK_COST,
+            Action::OpenDoor(_) => OPEN_DOOR_COST,
+            Action::Wait => WAIT_COST,
+            Action::Descend | Action::Ascend => STAIRS_COST,
+            Action::PickUp => PICK_UP_COST,
+            Action::Drop(_) => DROP_COST,
+            Action::UseItem(_) | Action::UseItemAt(..) => USE_ITEM_COST,
+            Action::Equip(_) => EQUIP_COST,
+        }
+    }
+}
+
+/// Sent to have an actor perform an action on its turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionEvent {
+    pub actor: Entity,
+    pub action: Action,
+}
+
+/// Why an action wasn't performed. The player's turn goes on, other actors
+/// wait instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionError {
+    /// The actor isn't taking a turn, or has already acted this turn.
+    NotYourTurn,
+    /// Something is in the way, ie: walking into a wall.
+    Blocked,
+    /// Shown to the player, ie: "Your inventory is full."
+    Invalid(String),
+}
+
+/// Sent once an [ActionEvent] has been resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionOutcome {
+    pub actor: Entity,
+    pub action: Action,
+    pub result: Result<(), ActionError>,
+}
+
+fn invalid(message: &str) -> ActionError {
+    ActionError::Invalid(message.to_string())
+}
+
+/// Check the actor can move onto `to`, returning the energy it costs.
+pub fn check_move(map: &Map, obstacles: &MapObstacles, to: IVec2) -> Result<i32, ActionError> {
+    if !map.0.in_bounds(to) || obstacles.0[to] || !map.0[to].is_walkable() {
+        return Err(ActionError::Blocked);
+    }
+    Ok(map.0[to].properties().move_cost)
+}
+
+/// Check there's a door at `at`, returning the tile it opens into.
+pub fn check_open(map: &Map, at: IVec2) -> Result<MapTile, ActionError> {
+    if !map.0.in_bounds(at) {
+        return Err(ActionError::Blocked);
+    }
+    map.0[at].properties().opens_to.ok_or_else(|| invalid("There is no door there."))
+}
+
+/// Check the actor is standing on the stairs needed for `action`.
+pub fn check_stairs(map: &Map, at: IVec2, action: Action) -> Result<ChangeLevelEvent, ActionError> {
+    let (stairs, ev, dir) = match action {
+        Action::Descend => (MapTile::DownStairs, ChangeLevelEvent::Descend, "down"),
+        _ => (MapTile::UpStairs, ChangeLevelEvent::Ascend, "up"),
+    };
+    if map.0[at] != stairs {
+        return Err(ActionError::Invalid(format!("There are no stairs {} here.", dir)));
+    }
+    Ok(ev)
+}
+
+/// Validate and apply actions, charging the actor's energy for each one
+/// that succeeds. Moving onto a tile applies its effect, see [enter_tile].
+///
+/// Actors other than the player are charged for waiting when their action
+/// fails, so a monster that picks something it can't do doesn't hold up the
+/// turn queue.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn resolve_actions(
+    mut commands: Commands,
+    mut evt_action: EventReader<ActionEvent>,
+    mut evt_outcome: EventWriter<ActionOutcome>,
+    mut evt_target: EventWriter<TargetEvent>,
+    mut evt_level: EventWriter<ChangeLevelEvent>,
+    mut q_actors: Query<
+        (
+            &mut Position,
+            &mut Energy,
+            &AttackDice,
+            &MapView,
+            Option<&mut Movement>,
+            Option<&mut Inventory>,
+            Option<&mut Equipment>,
+            Option<(&Name, &mut HitPoints)>,
+        ),
+        (With<Actor>, With<TakingATurn>),
+    >,
+    q_players: Query<&Player>,
+    q_monsters: Query<&Monster>,
+    q_items: Query<(Entity, &Position, &Name), (With<Item>, Without<Actor>)>,
+    q_item_info: Query<(&Name, Option<&Consumable>, Option<&Equippable>), With<Item>>,
+    mut q_map: Query<&mut Map>,
+    mut obstacles: ResMut<MapObstacles>,
+    mut actors: ResMut<MapActors>,
+    mut rng: ResMut<DiceRng>,
+    mut log: ResMut<PrintLog>,
+) {
+    let mut map = match q_map.get_single_mut() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
+
+    for ev in evt_action.iter() {
+        let actor = ev.actor;
+        let is_player = q_players.get(actor).is_ok();
+
+        let result = match q_actors.get_mut(actor) {
+            // One action per turn
+            Ok((_, energy, ..)) if energy.0 < TURN_ENERGY => Err(ActionError::NotYourTurn),
+            Err(_) => Err(ActionError::NotYourTurn),
+            Ok((mut pos, mut energy, dice, view, movement, mut inventory, mut equipment, combatant)) => {
+                let cost = match ev.action {
+                    Action::Move(dir) => {
+                        let dir = IVec2::from(dir);
+                        let (curr, next) = (pos.0, pos.0 + dir);
+                        check_move(&map, &obstacles, next).map(|cost| {
+                            actors.0[curr] = None;
+                            actors.0[next] = Some(actor);
+                            obstacles.0[curr] = false;
+                            obstacles.0[next] = true;
+                            pos.0 = next;
+                            if let Some(mut movement) = movement {
+                                movement.0 = dir;
+                            }
+                            if let Some((name, mut hp)) = combatant {
+                                enter_tile(map.0[next], name, &mut hp, &mut log);
+                            }
+                            cost
+                        })
+                    },
+                    Action::Attack(dir) => {
+                        let next = pos.0 + IVec2::from(dir);
+                        let target = map.0.in_bounds(next).then(|| actors.0[next]).flatten();
+                        match target {
+                            // The player and monsters only fight each other
+                            Some(target) if q_players.get(target).is_ok() != is_player => {
+                                evt_target.send(TargetEvent {
+                                    actor,
+                                    target,
+                                    effect: ActorEffect::Damage(rng.roll(dice.0)),
+                                });
+                                Ok(ATTACK_COST)
+                            },
+                            _ => Err(invalid("There is nothing there to attack.")),
+                        }
+                    },
+                    Action::OpenDoor(dir) => {
+                        let next = pos.0 + IVec2::from(dir);
+                        check_open(&map, next).map(|opened| {
+                            map.0[next] = opened;
+                            OPEN_DOOR_COST
+                        })
+                    },
+                    Action::Wait => Ok(WAIT_COST),
+                    Action::Descend | Action::Ascend => {
+                        if is_player {
+                            check_stairs(&map, pos.0, ev.action).map(|level| {
+                                evt_level.send(level);
+                                STAIRS_COST
+                            })
+                        } else {
+                            Err(invalid("Only the player can take the stairs."))
+                        }
+                    },
+                    Action::PickUp => match inventory.as_deref_mut() {
+                        Some(inventory) => pick_up(&mut commands, pos.0, inventory, &q_items, &mut log),
+                        None => Err(invalid("You can't carry anything.")),
+                    },
+                    Action::Drop(index) => match (inventory.as_deref_mut(), equipment.as_deref_mut()) {
+                        (Some(inventory), equipment) => {
+                            drop_item(&mut commands, pos.0, index, inventory, equipment, &q_item_info, &mut log)
+                        },
+                        _ => Err(invalid("You can't carry anything.")),
+                    },
+                    Action::UseItem(index) | Action::UseItemAt(index, _) => match (inventory.as_deref_mut(), equipment.as_deref_mut()) {
+                        (Some(inventory), equipment) => {
+                            let aim = match ev.action {
+                                Action::UseItemAt(_, at) => Some(IVec2::from(at)),
+                                _ => None,
+                            };
+                            // Without a target, aim at the closest monster
+                            let aim = aim.or_else(|| {
+                                let range = item_range(inventory, index, &q_item_info)?;
+                                let monsters: Vec<Position> = actors.0.iter().enumerate()
+                                    .filter(|(_, e)| e.map_or(false, |e| q_monsters.get(e).is_ok()))
+                                    .map(|(i, _)| Position(actors.0.index_to_pos(i)))
+                                    .collect();
+                                visible_targets(pos.0, view, range, monsters.iter()).first().copied()
+                            });
+                            let user = ItemUser { entity: actor, pos: pos.0, aim };
+                            use_item(&mut commands, user, index, inventory, equipment, &q_item_info, &map, &actors, &mut evt_target, &mut log)
+                        },
+                        _ => Err(invalid("You have nothing to use.")),
+                    },
+                    Action::Equip(index) => match (inventory.as_deref(), equipment.as_deref_mut()) {
+                        (Some(inventory), Some(equipment)) => equip(index, inventory, equipment, &q_item_info, &mut log),
+                        _ => Err(invalid("You can't equip anything.")),
+                    },
+                };
+                match cost {
+                    Ok(cost) => {
+                        energy.spend(cost);
+                        Ok(())
+                    },
+                    Err(e) => {
+                        if !is_player {
+                            energy.spend(WAIT_COST);
+                        }
+                        Err(e)
+                    },
+                }
+            },
+        };
+
+        if let Err(ActionError::Invalid(message)) = &result {
+            if is_player {
+                log.push(message.clone());
+            }
+        }
+        evt_outcome.send(ActionOutcome {
+            actor,
+            action: ev.action,
+            result,
+        });
+    }
+}
+
+fn pick_up(
+    commands: &mut Commands,
+    pos: IVec2,
+    inventory: &mut Inventory,
+    q_items: &Query<(Entity, &Position, &Name), (With<Item>, Without<Actor>)>,
+    log: &mut PrintLog,
+) -> Result<i32, ActionError> {
+    let (item, _, name) = match q_items.iter().find(|(_, item_pos, _)| item_pos.0 == pos) {
+        Some(item) => item,
+        None => return Err(invalid("There is nothing here to pick up.")),
+    };
+    if inventory.is_full() {
+        return Err(invalid("Your inventory is full."));
+    }
+
+    commands.entity(item).remove::<Position>();
+    inventory.items.push(item);
+    log.push(format!("You pick up the {}.", name.as_str()));
+    Ok(PICK_UP_COST)
+}
+
+fn drop_item(
+    commands: &mut Commands,
+    pos: IVec2,
+    index: usize,
+    inventory: &mut Inventory,
+    equipment: Option<&mut Equipment>,
+    q_item_info: &Query<(&Name, Option<&Consumable>, Option<&Equippable>), With<Item>>,
+    log: &mut PrintLog,
+) -> Result<i32, ActionError> {
+    if index >= inventory.items.len() {
+        return Err(invalid("There is no item in that slot."));
+    }
+    let item = inventory.items.remove(index);
+    if let Some(equipment) = equipment {
+        equipment.unequip(item);
+    }
+    commands.entity(item).insert(Position(pos));
+    if let Ok((name, ..)) = q_item_info.get(item) {
+        log.push(format!("You drop the {}.", name.as_str()));
+    }
+    Ok(DROP_COST)
+}
+
+/// How far the item in the given slot can reach, if it's aimed.
+fn item_range(
+    inventory: &Inventory,
+    index: usize,
+    q_item_info: &Query<(&Name, Option<&Consumable>, Option<&Equippable>), With<Item>>,
+) -> Option<u32> {
+    let item = inventory.items.get(index)?;
+    let (_, consumable, _) = q_item_info.get(*item).ok()?;
+    consumable?.effect.range()
+}
+
+/// The actor using an item and where it's aiming.
+struct ItemUser {
+    entity: Entity,
+    pos: IVec2,
+    aim: Option<IVec2>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn use_item(
+    commands: &mut Commands,
+    user: ItemUser,
+    index: usize,
+    inventory: &mut Inventory,
+    equipment: Option<&mut Equipment>,
+    q_item_info: &Query<(&Name, Option<&Consumable>, Option<&Equippable>), With<Item>>,
+    map: &Map,
+    actors: &MapActors,
+    evt_target: &mut EventWriter<TargetEvent>,
+    log: &mut PrintLog,
+) -> Result<i32, ActionError> {
+    let item = match inventory.items.get(index) {
+        Some(item) => *item,
+        None => return Err(invalid("There is no item in that slot.")),
+    };
+    let (name, consumable) = match q_item_info.get(item) {
+        Ok((name, consumable, _)) => (name, consumable),
+        Err(_) => return Err(invalid("There is no item in that slot.")),
+    };
+    let consumable = match consumable {
+        Some(consumable) => consumable,
+        None => return Err(ActionError::Invalid(format!("You can't use the {}.", name.as_str()))),
+    };
+
+    let target = match consumable.effect {
+        ItemEffect::Heal(amount) => Some((user.entity, ActorEffect::Heal(amount))),
+        ItemEffect::Damage { amount, range } => {
+            let aim = match user.aim {
+                Some(aim) if aim != user.pos && in_range(user.pos, aim, range) => aim,
+                _ => return Err(invalid("There is nothing in range.")),
+            };
+            line_of_fire(user.pos, aim, map, actors).hit
+                .map(|hit| (hit, ActorEffect::Damage(amount)))
+        },
+    };
+
+    log.push(consumable.message.clone());
+    match target {
+        Some((target, effect)) => evt_target.send(TargetEvent {
+            actor: user.entity,
+            target,
+            effect,
+        }),
+        None => log.push("It hits nothing.".to_string()),
+    }
+
+    inventory.items.remove(index);
+    // Items can be both worn and used up
+    if let Some(equipment) = equipment {
+        equipment.unequip(item);
+    }
+    commands.entity(item).despawn();
+    Ok(USE_ITEM_COST)
+}
+
+fn equip(
+    index: usize,
+    inventory: &Inventory,
+    equipment: &mut Equipment,
+    q_item_info: &Query<(&Name, Option<&Consumable>, Option<&Equippable>), With<Item>>,
+    log: &mut PrintLog,
+) -> Result<i32, ActionError> {
+    let item = match inventory.items.get(index) {
+        Some(item) => *item,
+        None => return Err(invalid("There is no item in that slot.")),
+    };
+    let (name, equippable) = match q_item_info.get(item) {
+        Ok((name, _, equippable)) => (name.as_str(), equippable),
+        Err(_) => ("???", None),
+    };
+    let slot = match equippable {
+        Some(equippable) => equippable.slot,
+        None => return Err(ActionError::Invalid(format!("You can't equip the {}.", name))),
+    };
+
+    if equipment.slot_of(item).is_some() {
+        equipment.unequip(item);
+        log.push(format!("You unequip the {}.", name));
+    } else {
+        if let Some(old) = equipment.slots.insert(slot, item) {
+            let old_name = q_item_info.get(old).map_or("???", |(name, ..)| name.as_str());
+            log.push(format!("You unequip the {}.", old_name));
+        }
+        log.push(format!("You equip the {} on your {}.", name, slot));
+    }
+    Ok(EQUIP_COST)
+}
+
+#[cfg(test)]
+mod test {
+    use sark_grids::Grid;
+
+    use crate::{
+        equipment::EquipSlot,
+        map::TileEffect,
+        test_util::{map_app, send, sent, spawn_actor},
+    };
+
+    use super::*;
+
+    fn lava() -> Map {
+        let mut map = Map(Grid::default([3, 1]));
+        map.0[[0, 0]] = MapTile::Floor;
+        map.0[[1, 0]] = MapTile::Lava;
+        map
+    }
+
+    fn corridor() -> (Map, MapObstacles) {
+        let mut map = Map(Grid::default([10, 3]));
+        for x in 1..9 {
+            map.0[[x, 1]] = MapTile::Floor;
+        }
+        let obstacles = MapObstacles::from_map(&map);
+        (map, obstacles)
+    }
+
+    /// Resolve a single action, returning the outcomes sent so far.
+    fn resolve(app: &mut App, actor: Entity, action: Action) -> Vec<ActionOutcome> {
+        send(app, ActionEvent { actor, action });
+        sent(app)
+    }
+
+    #[test]
+    fn moving_updates_the_map_state() {
+        let mut app = map_app(corridor().0);
+        app.add_plugin(ActionPlugin).add_event::<TargetEvent>().add_event::<ChangeLevelEvent>();
+        let actor = spawn_actor(&mut app, IVec2::new(2, 1), (AttackDice::default(), MapView::default()));
+        let outcomes = resolve(&mut app, actor, Action::Move([1, 0]));
+
+        assert_eq!(vec![ActionOutcome { actor, action: Action::Move([1, 0]), result: Ok(()) }], outcomes);
+        assert_eq!(IVec2::new(3, 1), app.world.get::<Position>(actor).unwrap().0);
+        assert_eq!(TURN_ENERGY - MOVE_COST, app.world.get::<Energy>(actor).unwrap().0);
+
+        let obstacles = app.world.resource::<MapObstacles>();
+        assert!(!obstacles.0[[2, 1]]);
+        assert!(obstacles.0[[3, 1]]);
+        let actors = app.world.resource::<MapActors>();
+        assert_eq!(None, actors.0[[2, 1]]);
+        assert_eq!(Some(actor), actors.0[[3, 1]]);
+
+        // Already acted this turn
+        let outcomes = resolve(&mut app, actor, Action::Move([1, 0]));
+        assert_eq!(Err(ActionError::NotYourTurn), outcomes.last().unwrap().result);
+        assert_eq!(IVec2::new(3, 1), app.world.get::<Position>(actor).unwrap().0);
+    }
+
+    #[test]
+    fn moving_onto_lava_burns() {
+        let mut app = map_app(lava());
+        app.add_plugin(ActionPlugin).add_event::<TargetEvent>().add_event::<ChangeLevelEvent>();
+        let rat = (AttackDice::default(), MapView::default(), Name::new("Rat"), HitPoints(10));
+        let rat = spawn_actor(&mut app, IVec2::new(0, 0), rat);
+        let outcomes = resolve(&mut app, rat, Action::Move([1, 0]));
+
+        let burn = match MapTile::Lava.properties().on_enter {
+            Some(TileEffect::Burn(amount)) => amount,
+            _ => 0,
+        };
+        assert_eq!(Ok(()), outcomes[0].result);
+        assert!(burn > 0);
+        assert_eq!(10 - burn, app.world.get::<HitPoints>(rat).unwrap().0);
+    }
+
+    #[test]
+    fn spawning_on_lava_doesnt_burn() {
+        let mut app = map_app(lava());
+        app.add_plugin(ActionPlugin).add_event::<TargetEvent>().add_event::<ChangeLevelEvent>();
+        let rat = (AttackDice::default(), MapView::default(), Name::new("Rat"), HitPoints(10));
+        let rat = spawn_actor(&mut app, IVec2::new(1, 0), rat);
+        resolve(&mut app, rat, Action::Wait);
+
+        assert_eq!(10, app.world.get::<HitPoints>(rat).unwrap().0);
+    }
+
+    #[test]
+    fn failed_actions_cost_monsters_a_wait() {
+        let mut app = map_app(corridor().0);
+        app.add_plugin(ActionPlugin).add_event::<TargetEvent>().add_event::<ChangeLevelEvent>();
+        let actor = spawn_actor(&mut app, IVec2::new(2, 1), (AttackDice::default(), MapView::default()));
+        let outcomes = resolve(&mut app, actor, Action::Move([0, 1]));
+
+        assert_eq!(Err(ActionError::Blocked), outcomes[0].result);
+        assert_eq!(IVec2::new(2, 1), app.world.get::<Position>(actor).unwrap().0);
+        assert_eq!(TURN_ENERGY - WAIT_COST, app.world.get::<Energy>(actor).unwrap().0);
+    }
+
+    #[test]
+    fn failed_actions_are_free_for_the_player() {
+        let mut app = map_app(corridor().0);
+        app.add_plugin(ActionPlugin).add_event::<TargetEvent>().add_event::<ChangeLevelEvent>();
+        let actor = spawn_actor(&mut app, IVec2::new(2, 1), (AttackDice::default(), MapView::default(), Player));
+        let outcomes = resolve(&mut app, actor, Action::Move([0, 1]));
+
+        assert_eq!(Err(ActionError::Blocked), outcomes[0].result);
+        assert_eq!(TURN_ENERGY, app.world.get::<Energy>(actor).unwrap().0);
+    }
+
+    #[test]
+    fn used_up_equipment_is_unequipped() {
+        let mut app = map_app(corridor().0);
+        app.add_plugin(ActionPlugin).add_event::<TargetEvent>().add_event::<ChangeLevelEvent>();
+        let consumable = Consumable {
+            effect: ItemEffect::Heal(1),
+            message: String::new(),
+        };
+        let equippable = Equippable {
+            slot: EquipSlot::Head,
+            attack: None,
+            strength: 0,
+            defense: 1,
+        };
+        let item = app.world.spawn()
+            .insert_bundle((Item, Name::new("Mushroom Cap"), consumable, equippable))
+            .id();
+        let mut inventory = Inventory::new(1);
+        inventory.items.push(item);
+        let mut equipment = Equipment::default();
+        equipment.slots.insert(EquipSlot::Head, item);
+        let actor = spawn_actor(&mut app, IVec2::new(2, 1), (AttackDice::default(), MapView::default(), inventory, equipment));
+
+        let outcomes = resolve(&mut app, actor, Action::UseItem(0));
+        assert_eq!(Ok(()), outcomes[0].result);
+        assert!(app.world.get_entity(item).is_none());
+        assert!(app.world.get::<Inventory>(actor).unwrap().items.is_empty());
+        assert_eq!(None, app.world.get::<Equipment>(actor).unwrap().slot_of(item));
+    }
+
+    #[test]
+    fn moves_are_checked_against_the_map() {
+        let (mut map, mut obstacles) = corridor();
+
+        assert_eq!(Ok(MOVE_COST), check_move(&map, &obstacles, IVec2::new(2, 1)));
+        assert_eq!(Err(ActionError::Blocked), check_move(&map, &obstacles, IVec2::new(2, 2)));
+        assert_eq!(Err(ActionError::Blocked), check_move(&map, &obstacles, IVec2::new(2, 5)));
+
+        map.0[[3, 1]] = MapTile::ShallowWater;
+        assert_eq!(Ok(MapTile::ShallowWater.properties().move_cost), check_move(&map, &obstacles, IVec2::new(3, 1)));
+
+        // Another actor in the way
+        obstacles.0[[4, 1]] = true;
+        assert_eq!(Err(ActionError::Blocked), check_move(&map, &obstacles, IVec2::new(4, 1)));
+    }
+
+    #[test]
+    fn doors_are_opened_before_walking_through() {
+        let (mut map, obstacles) = corridor();
+        map.0[[5, 1]] = MapTile::ClosedDoor;
+
+        assert_eq!(Err(ActionError::Blocked), check_move(&map, &obstacles, IVec2::new(5, 1)));
+        assert_eq!(Ok(MapTile::OpenDoor), check_open(&map, IVec2::new(5, 1)));
+        assert!(check_open(&map, IVec2::new(4, 1)).is_err());
+    }
+
+    #[test]
+    fn stairs_must_be_underfoot() {
+        let (mut map, _) = corridor();
+        map.0[[2, 1]] = MapTile::DownStairs;
+
+        assert_eq!(Ok(ChangeLevelEvent::Descend), check_stairs(&map, IVec2::new(2, 1), Action::Descend));
+        assert!(check_stairs(&map, IVec2::new(2, 1), Action::Ascend).is_err());
+        assert!(check_stairs(&map, IVec2::new(3, 1), Action::Descend).is_err());
+    }
+}
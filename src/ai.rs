@@ -0,0 +1,341 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action::{check_move, Action, ActionEvent},
+    combat::{HitPoints, MaxHitPoints},
+    dijkstra::{GoalMaps, PlayerMaps, DIRECTIONS},
+    map::Map,
+    map_state::MapObstacles,
+    monster::Monster,
+    movement::Position,
+    player::Player,
+    rng::DiceRng,
+    turn_system::{TakingATurn, TurnQueue},
+    visibility::MapView,
+};
+
+/// Label for the system deciding monster actions. Occurs in [CoreStage::Update].
+pub const MONSTER_AI_SYSTEM_LABEL: &str = "monster_ai";
+
+/// How many random tiles a patrolling monster tries before standing still.
+const PATROL_ATTEMPTS: usize = 10;
+
+/// How a kind of monster behaves, read from its
+/// [crate::monster::MonsterTemplate].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AiSettings {
+    /// What the monster does before it has seen the player.
+    pub idle: IdleBehavior,
+    /// The monster flees once its hp drops to this fraction of its max hp.
+    /// At 0.0 it never flees.
+    pub flee_below: f32,
+    /// How many turns the monster flees for before going back to idle.
+    pub flee_turns: u32,
+    /// How many turns the monster searches for the player after losing sight
+    /// of them before going back to idle.
+    pub search_turns: u32,
+}
+
+impl Default for AiSettings {
+    fn default() -> Self {
+        Self {
+            idle: IdleBehavior::Wander,
+            flee_below: 0.0,
+            flee_turns: 10,
+            search_turns: 8,
+        }
+    }
+}
+
+impl AiSettings {
+    /// Check for values that can't be used in game.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.flee_below) {
+            return Err(format!("flee_below is {}, it must be from 0.0 to 1.0", self.flee_below));
+        }
+        if let IdleBehavior::Patrol { radius: 0 } = self.idle {
+            return Err("patrol radius must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What a monster does while it's [AiState::Idle].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdleBehavior {
+    /// Wait in place.
+    Stand,
+    /// Take a random step each turn.
+    Wander,
+    /// Walk to random spots within the given distance.
+    Patrol { radius: u32 },
+}
+
+/// What a monster is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AiState {
+    /// The monster hasn't seen the player, see [IdleBehavior].
+    Idle {
+        /// Where a patrolling monster is headed.
+        goal: Option<IVec2>,
+    },
+    /// The player is in view.
+    Chase { last_seen: IVec2 },
+    /// The player was lost sight of, the monster heads to where they were last seen.
+    Search { last_seen: IVec2, turns: u32 },
+    /// Running away from the player.
+    Flee { from: IVec2, turns: u32 },
+}
+
+impl Default for AiState {
+    fn default() -> Self {
+        AiState::Idle { goal: None }
+    }
+}
+
+/// A monster's behaviour and what it's currently doing.
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MonsterAi {
+    pub settings: AiSettings,
+    pub state: AiState,
+}
+
+impl MonsterAi {
+    pub fn new(settings: AiSettings) -> Self {
+        Self {
+            settings,
+            state: AiState::default(),
+        }
+    }
+
+    /// Move to the next state given what the monster can see at the start
+    /// of its turn.
+    pub fn update(&mut self, pos: IVec2, player: Option<IVec2>, hp_fraction: f32) {
+        let settings = &self.settings;
+        let wants_to_flee = settings.flee_below > 0.0 && hp_fraction <= settings.flee_below;
+
+        self.state = match (self.state, player) {
+            // Fleeing lasts until it times out, even if the player is still in view
+            (AiState::Flee { from, turns }, player) => match turns {
+                0 => AiState::default(),
+                _ => AiState::Flee { from: player.unwrap_or(from), turns: turns - 1 },
+            },
+            (_, Some(player)) if wants_to_flee => AiState::Flee { from: player, turns: settings.flee_turns },
+            (_, Some(player)) => AiState::Chase { last_seen: player },
+            (AiState::Chase { last_seen }, None) => AiState::Search { last_seen, turns: settings.search_turns },
+            (AiState::Search { last_seen, turns }, None) => {
+                if turns == 0 || pos == last_seen {
+                    AiState::default()
+                } else {
+                    AiState::Search { last_seen, turns: turns - 1 }
+                }
+            },
+            (AiState::Idle { goal }, None) => AiState::Idle { goal: goal.filter(|goal| *goal != pos) },
+        };
+    }
+}
+
+/// Decide what each monster does on its turn.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn monster_ai(
+    obstacles: Res<MapObstacles>,
+    queue: Res<TurnQueue>,
+    q_player: Query<&Position, With<Player>>,
+    mut q_monster: Query<
+        (&Position, &MapView, &HitPoints, &MaxHitPoints, &mut MonsterAi, ChangeTrackers<TakingATurn>),
+        (With<Monster>, Without<Player>, With<TakingATurn>),
+    >,
+    q_map: Query<&Map>,
+    player_maps: Res<PlayerMaps>,
+    mut goal_maps: ResMut<GoalMaps>,
+    mut rng: ResMut<DiceRng>,
+    mut evt_action: EventWriter<ActionEvent>,
+) {
+    let map = match q_map.get_single() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
+    let player_pos = q_player.get_single().ok().map(|p| p.0);
+
+    // Monsters act in turn order so ties always play out the same way
+    for &entity in queue.acting() {
+        let (pos, view, hp, max_hp, mut ai, turn) = match q_monster.get_mut(entity) {
+            Ok(monster) => monster,
+            Err(_) => continue,
+        };
+        let pos = pos.0;
+
+        // The state only changes once per turn, so search and flee times
+        // are counted in turns
+        if turn.is_added() {
+            let seen = player_pos.filter(|p| view.0.in_bounds(*p) && view.0[*p]);
+            ai.update(pos, seen, hp.0 as f32 / max_hp.0.max(1) as f32);
+        }
+        let adjacent_player = player_pos.filter(|p| (*p - pos).abs().max_element() == 1);
+
+        let (state, idle) = (ai.state, ai.settings.idle);
+        let action = match (state, adjacent_player) {
+            (AiState::Chase { .. }, Some(player)) => Action::Attack((player - pos).into()),
+            // Every monster chasing the player shares the same map
+            (AiState::Chase { .. }, None) => match player_maps.approach.next_step(pos, |p| obstacles.0[p]) {
+                Some(next) => step_action(map, pos, next),
+                None => Action::Wait,
+            },
+            (AiState::Search { last_seen, .. }, _) => step_towards(map, &obstacles, &mut goal_maps, pos, last_seen, player_pos),
+            (AiState::Flee { .. }, _) => match player_maps.flee.next_step(pos, |p| obstacles.0[p]) {
+                Some(next) => step_action(map, pos, next),
+                // Cornered
+                None => adjacent_player.map_or(Action::Wait, |player| Action::Attack((player - pos).into())),
+            },
+            (AiState::Idle { goal }, _) => match idle {
+                IdleBehavior::Stand => Action::Wait,
+                IdleBehavior::Wander => {
+                    // One roll in nine stays put
+                    let roll = rng.range(0, DIRECTIONS.len() as i32 + 1) as usize;
+                    match DIRECTIONS.get(roll) {
+                        Some(dir) if check_move(map, &obstacles, pos + IVec2::from(*dir)).is_ok() => Action::Move(*dir),
+                        _ => Action::Wait,
+                    }
+                },
+                IdleBehavior::Patrol { radius } => {
+                    let goal = goal.or_else(|| patrol_goal(map, &obstacles, pos, radius, &mut rng));
+                    ai.state = AiState::Idle { goal };
+                    match goal {
+                        Some(goal) => step_towards(map, &obstacles, &mut goal_maps, pos, goal, player_pos),
+                        None => Action::Wait,
+                    }
+                },
+            },
+        };
+
+        // Give up on patrol goals that can't be reached
+        if action == Action::Wait {
+            if let AiState::Idle { goal: Some(_) } = ai.state {
+                ai.state = AiState::default();
+            }
+        }
+
+        evt_action.send(ActionEvent { actor: entity, action });
+    }
+}
+
+/// Step towards a goal other than the player, attacking the player if
+/// they're in the way and opening doors along the way.
+fn step_towards(
+    map: &Map,
+    obstacles: &MapObstacles,
+    goal_maps: &mut GoalMaps,
+    pos: IVec2,
+    goal: IVec2,
+    player: Option<IVec2>,
+) -> Action {
+    // Other monsters are in the way, the player can be attacked
+    let next = goal_maps.get(map, goal).next_step(pos, |p| obstacles.0[p] && Some(p) != player);
+    match next {
+        Some(next) if Some(next) == player => Action::Attack((next - pos).into()),
+        Some(next) => step_action(map, pos, next),
+        None => Action::Wait,
+    }
+}
+
+/// Step onto `next`, opening it first if it's a door.
+fn step_action(map: &Map, pos: IVec2, next: IVec2) -> Action {
+    let dir = (next - pos).into();
+    if map.0[next].properties().opens_to.is_some() {
+        Action::OpenDoor(dir)
+    } else {
+        Action::Move(dir)
+    }
+}
+
+/// Pick a random walkable tile near `pos` for a patrolling monster to walk to.
+fn patrol_goal(map: &Map, obstacles: &MapObstacles, pos: IVec2, radius: u32, rng: &mut DiceRng) -> Option<IVec2> {
+    let radius = radius as i32;
+    (0..PATROL_ATTEMPTS).find_map(|_| {
+        let offset = IVec2::new(rng.range(-radius, radius + 1), rng.range(-radius, radius + 1));
+        let goal = pos + offset;
+        if goal != pos && check_move(map, obstacles, goal).is_ok() {
+            Some(goal)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fleeing_ai() -> MonsterAi {
+        MonsterAi::new(AiSettings {
+            flee_below: 0.5,
+            flee_turns: 2,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn chase_then_search_then_idle() {
+        let mut ai = MonsterAi::new(AiSettings {
+            search_turns: 1,
+            ..Default::default()
+        });
+        let pos = IVec2::ZERO;
+        let player = IVec2::new(3, 0);
+
+        ai.update(pos, Some(player), 1.0);
+        assert_eq!(AiState::Chase { last_seen: player }, ai.state);
+
+        ai.update(pos, None, 1.0);
+        assert_eq!(AiState::Search { last_seen: player, turns: 1 }, ai.state);
+        ai.update(pos, None, 1.0);
+        assert_eq!(AiState::Search { last_seen: player, turns: 0 }, ai.state);
+        ai.update(pos, None, 1.0);
+        assert_eq!(AiState::default(), ai.state);
+    }
+
+    #[test]
+    fn searching_stops_at_last_seen_position() {
+        let mut ai = MonsterAi::new(AiSettings::default());
+        let player = IVec2::new(3, 0);
+
+        ai.update(IVec2::ZERO, Some(player), 1.0);
+        ai.update(IVec2::ZERO, None, 1.0);
+        ai.update(player, None, 1.0);
+        assert_eq!(AiState::default(), ai.state);
+    }
+
+    #[test]
+    fn flee_at_low_hp_until_timeout() {
+        let mut ai = fleeing_ai();
+        let player = IVec2::new(1, 0);
+
+        ai.update(IVec2::ZERO, Some(player), 0.8);
+        assert_eq!(AiState::Chase { last_seen: player }, ai.state);
+
+        ai.update(IVec2::ZERO, Some(player), 0.4);
+        assert_eq!(AiState::Flee { from: player, turns: 2 }, ai.state);
+        ai.update(IVec2::ZERO, None, 0.4);
+        ai.update(IVec2::ZERO, None, 0.4);
+        assert_eq!(AiState::Flee { from: player, turns: 0 }, ai.state);
+        ai.update(IVec2::ZERO, None, 0.4);
+        assert_eq!(AiState::default(), ai.state);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let settings = AiSettings {
+            flee_below: 1.5,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = AiSettings {
+            idle: IdleBehavior::Patrol { radius: 0 },
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Generate levels without running the game.
+//!
+//! ```text
+//! mapgen [--settings <path>] [--seed <n>] [--depth <n>] [--png <path>] [--scale <n>]
+//! mapgen --seeds <first>..<end> [--settings <path>] [--depth <n>]
+//! ```
+//!
+//! A single level is printed as ascii, or written to a png with `--png`.
+//! With `--seeds` every level in the range is generated and only the stats
+//! are printed. Exits with an error if any level has floor the player can't reach.
+
+use std::{fs::File, io::BufWriter, ops::Range, path::Path, process::exit};
+
+use bevy::prelude::*;
+use bevy_ascii_terminal::Tile;
+use bevy_roguelike::{
+    config::{self, MapGenSettings},
+    map::{level_rng, MapGenAssets},
+    map_gen::{build_level, BuildState, MapStats, Vaults},
+    ItemTemplates, MonsterTemplates,
+};
+
+const USAGE: &str = "\
+Usage:
+    mapgen [--settings <path>] [--seed <n>] [--depth <n>] [--png <path>] [--scale <n>]
+    mapgen --seeds <first>..<end> [--settings <path>] [--depth <n>]";
+
+/// Pixels per tile in png output.
+const DEFAULT_SCALE: u32 = 4;
+
+fn main() {
+    if config::has_arg("--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let settings = match config::arg_value("--settings") {
+        Some(path) => config::load_map_settings(Path::new(&path))?,
+        None => config::try_get_map_settings()?,
+    };
+    let depth = parse_arg("--depth")?.unwrap_or(1);
+
+    let monsters = MonsterTemplates::try_load()?;
+    let items = ItemTemplates::try_load()?;
+    let vaults = Vaults::try_load()?;
+    vaults.validate_spawns(&monsters, &items)?;
+    let assets = MapGenAssets {
+        monsters: &monsters,
+        items: &items,
+        vaults: &vaults,
+    };
+
+    if let Some(seeds) = config::arg_value("--seeds") {
+        return batch(&settings, &assets, parse_range(&seeds)?, depth);
+    }
+
+    let seed = parse_arg("--seed")?.unwrap_or(settings.seed);
+    let state = build_level(&settings, &assets, &mut level_rng(seed, depth), depth, false);
+
+    match config::arg_value("--png") {
+        Some(path) => {
+            let scale = parse_arg("--scale")?.unwrap_or(DEFAULT_SCALE).max(1);
+            write_png(&state, &assets, Path::new(&path), scale)?;
+            println!("Wrote {}", path);
+        },
+        None => print!("{}", to_ascii(&state, &assets)),
+    }
+    println!("seed {}: {}", seed, MapStats::new(&state));
+
+    Ok(())
+}
+
+/// Generate a level for each seed and print the stats.
+fn batch(settings: &MapGenSettings, assets: &MapGenAssets, seeds: Range<u64>, depth: u32) -> Result<(), String> {
+    let mut all = Vec::new();
+    for seed in seeds.clone() {
+        let state = build_level(settings, assets, &mut level_rng(seed, depth), depth, false);
+        let stats = MapStats::new(&state);
+        println!("seed {}: {}", seed, stats);
+        all.push(stats);
+    }
+
+    if all.is_empty() {
+        return Err(format!("No seeds in {:?}", seeds));
+    }
+
+    let summary = |name: &str, value: fn(&MapStats) -> f32| {
+        let values = all.iter().map(value);
+        let min = values.clone().fold(f32::MAX, f32::min);
+        let max = values.clone().fold(f32::MIN, f32::max);
+        let average = values.sum::<f32>() / all.len() as f32;
+        println!("{:>10}: min {:.1}, average {:.1}, max {:.1}", name, min, average, max);
+    };
+    println!("\n{} levels at depth {}", all.len(), depth);
+    summary("floor %", |s| s.floor_percent);
+    summary("rooms", |s| s.rooms as f32);
+    summary("reachable %", |s| s.reachable_percent);
+    summary("monsters", |s| s.monsters as f32);
+    summary("items", |s| s.items as f32);
+
+    let disconnected = all.iter().filter(|s| !s.fully_connected()).count();
+    if disconnected > 0 {
+        return Err(format!("{} of {} levels have floor that can't be reached", disconnected, all.len()));
+    }
+    Ok(())
+}
+
+/// What's drawn on each tile: the map, then items, monsters and the player's start on top.
+fn tiles(state: &BuildState, assets: &MapGenAssets) -> Vec<Tile> {
+    let map = &state.map.0;
+    let mut tiles: Vec<Tile> = map.iter().map(|t| Tile::from(*t)).collect();
+
+    for spawn in state.items.iter() {
+        if let Some(item) = assets.items.get(&spawn.name) {
+            tiles[map.pos_to_index(spawn.position)] = Tile::from(&item.bundle().renderable);
+        }
+    }
+    for spawn in state.monsters.iter() {
+        if let Some(monster) = assets.monsters.get(&spawn.name) {
+            tiles[map.pos_to_index(spawn.position)] = Tile::from(&monster.bundle().movable.renderable);
+        }
+    }
+    tiles[map.pos_to_index(state.start())] = Tile {
+        glyph: '@',
+        fg_color: Color::WHITE,
+        bg_color: Color::BLACK,
+    };
+    tiles
+}
+
+fn to_ascii(state: &BuildState, assets: &MapGenAssets) -> String {
+    let size = state.map.0.size();
+    let tiles = tiles(state, assets);
+
+    // The map's y axis points up
+    let mut text = String::with_capacity(((size.x + 1) * size.y) as usize);
+    for row in tiles.chunks(size.x as usize).rev() {
+        text.extend(row.iter().map(|t| t.glyph));
+        text.push('\n');
+    }
+    text
+}
+
+fn write_png(state: &BuildState, assets: &MapGenAssets, path: &Path, scale: u32) -> Result<(), String> {
+    let size = state.map.0.size();
+    let tiles = tiles(state, assets);
+    let (width, height) = (size.x * scale, size.y * scale);
+
+    let mut data = Vec::with_capacity((width * height * 3) as usize);
+    for row in tiles.chunks(size.x as usize).rev() {
+        let mut line = Vec::with_capacity((width * 3) as usize);
+        for tile in row {
+            let [r, g, b, _]: [f32; 4] = tile.fg_color.into();
+            let rgb = [r, g, b].map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8);
+            for _ in 0..scale {
+                line.extend_from_slice(&rgb);
+            }
+        }
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Error creating {}: {}", path.display(), e)),
+    };
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let result = encoder.write_header().and_then(|mut writer| writer.write_image_data(&data));
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Error writing {}: {}", path.display(), e)),
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match config::arg_value(name) {
+        Some(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => Err(format!("Invalid {} '{}': {}", name, value, e)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parse a range of seeds, ie: `0..100`.
+fn parse_range(text: &str) -> Result<Range<u64>, String> {
+    let parsed = text
+        .split_once("..")
+        .and_then(|(start, end)| Some(start.parse().ok()?..end.parse().ok()?));
+    match parsed {
+        Some(range) => Ok(range),
+        None => Err(format!("Invalid --seeds '{}', expected a range like 0..100", text)),
+    }
+}
@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+use bevy_ascii_terminal::{Terminal, Tile};
+
+use crate::{
+    config, map::Map, movement::Position, player::{InputMode, Player}, render::RENDER_SYSTEM_LABEL,
+    targeting::Targeting, GAME_SIZE,
+};
+
+/// Label for the system moving the [MapViewport] to follow the player, or
+/// the cursor while targeting. Occurs in [CoreStage::Last].
+pub const VIEWPORT_SYSTEM_LABEL: &str = "update_viewport";
+
+/// The map is drawn inside the terminal's border.
+const SCREEN_OFFSET: IVec2 = IVec2::ONE;
+
+/// Scrolls the map to follow the player so maps can be larger than the screen.
+/// While targeting it follows the cursor instead.
+///
+/// Run with `--camera center` to keep the player in the center of the screen
+/// instead of stopping at the edges of the map.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        let mode = match config::arg_value("--camera").as_deref() {
+            Some("center") => CameraMode::Center,
+            _ => CameraMode::Clamp,
+        };
+        let size = IVec2::from([GAME_SIZE[0] as i32, GAME_SIZE[1] as i32]) - SCREEN_OFFSET * 2;
+
+        app.insert_resource(MapViewport::new(size, mode))
+        .add_system_to_stage(CoreStage::Last, update_viewport
+            .label(VIEWPORT_SYSTEM_LABEL)
+            .before(RENDER_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// How the [MapViewport] follows the player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Follow the player, but stop at the edges of the map. Maps smaller
+    /// than the screen are centered.
+    Clamp,
+    /// Always keep the player in the center of the screen.
+    Center,
+}
+
+/// The part of the map that's drawn to the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapViewport {
+    /// The map position drawn in the bottom left corner of the screen.
+    pub origin: IVec2,
+    /// How many map tiles fit on the screen.
+    pub size: IVec2,
+    pub mode: CameraMode,
+}
+
+impl MapViewport {
+    pub fn new(size: IVec2, mode: CameraMode) -> Self {
+        Self {
+            origin: IVec2::ZERO,
+            size,
+            mode,
+        }
+    }
+
+    /// Convert a map position to a position on the terminal.
+    pub fn to_screen(&self, map_pos: IVec2) -> IVec2 {
+        map_pos - self.origin + SCREEN_OFFSET
+    }
+
+    /// Whether a map position is on the screen.
+    pub fn contains(&self, map_pos: IVec2) -> bool {
+        let p = map_pos - self.origin;
+        p.cmpge(IVec2::ZERO).all() && p.cmplt(self.size).all()
+    }
+
+    /// The origin that shows `target` on a map of the given size.
+    pub fn focus(&self, target: IVec2, map_size: IVec2) -> IVec2 {
+        let centered = target - self.size / 2;
+        if self.mode == CameraMode::Center {
+            return centered;
+        }
+
+        let mut origin = centered;
+        for axis in 0..2 {
+            let spare = self.size[axis] - map_size[axis];
+            origin[axis] = if spare >= 0 {
+                -spare / 2
+            } else {
+                centered[axis].clamp(0, -spare)
+            };
+        }
+        origin
+    }
+
+    /// Draw a tile at a map position if it's on the screen.
+    pub fn put_tile(&self, term: &mut Terminal, map_pos: IVec2, tile: Tile) {
+        if self.contains(map_pos) {
+            term.put_tile(self.to_screen(map_pos), tile);
+        }
+    }
+}
+
+fn update_viewport(
+    mut viewport: ResMut<MapViewport>,
+    mode: Res<InputMode>,
+    targeting: Res<Targeting>,
+    q_player: Query<&Position, With<Player>>,
+    q_map: Query<&Map>,
+) {
+    let (player, map) = match (q_player.get_single(), q_map.get_single()) {
+        (Ok(player), Ok(map)) => (player, map),
+        _ => return,
+    };
+
+    let target = match *mode {
+        InputMode::Targeting => targeting.cursor,
+        _ => player.0,
+    };
+
+    // Only touch the viewport when it moves so rendering can tell it changed
+    let origin = viewport.focus(target, map.0.size().as_ivec2());
+    if viewport.origin != origin {
+        viewport.origin = origin;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clamp_stops_at_map_edges() {
+        let viewport = MapViewport::new(IVec2::new(20, 10), CameraMode::Clamp);
+        let map_size = IVec2::new(100, 50);
+
+        assert_eq!(IVec2::new(40, 20), viewport.focus(IVec2::new(50, 25), map_size));
+        assert_eq!(IVec2::ZERO, viewport.focus(IVec2::new(2, 1), map_size));
+        assert_eq!(IVec2::new(80, 40), viewport.focus(IVec2::new(99, 49), map_size));
+    }
+
+    #[test]
+    fn small_maps_are_centered() {
+        let viewport = MapViewport::new(IVec2::new(20, 10), CameraMode::Clamp);
+        let origin = viewport.focus(IVec2::new(3, 30), IVec2::new(10, 50));
+
+        assert_eq!(-5, origin.x);
+        assert_eq!(25, origin.y);
+    }
+
+    #[test]
+    fn screen_and_map_positions() {
+        let mut viewport = MapViewport::new(IVec2::new(20, 10), CameraMode::Center);
+        viewport.origin = viewport.focus(IVec2::new(2, 2), IVec2::new(100, 50));
+
+        let p = IVec2::new(2, 2);
+        assert!(viewport.contains(p));
+        assert!(!viewport.contains(IVec2::new(30, 2)));
+        // The player stays in the middle of the screen past the edge of the map
+        assert_eq!(IVec2::new(11, 6), viewport.to_screen(p));
+    }
+}
@@ -1,7 +1,8 @@
 use bevy::prelude::*;
-use bracket_random::prelude::DiceType;
+use bracket_random::prelude::{DiceType, parse_dice_string};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{ui::PrintLog, map_state::{MapObstacles, MapActors}, movement::Position};
+use crate::{ui::PrintLog, map_state::{MapObstacles, MapActors}, movement::Position, map::{MapTile, TileEffect}};
 
 pub const RESOLVE_TARGET_EVENTS_SYSTEM_LABEL: &str = "resolve_target_events";
 pub const DEATH_SYSTEM_LABEL: &str = "death_system";
@@ -21,21 +22,58 @@ impl Plugin for CombatPlugin {
     }
 }
 
-#[derive(Debug, Component)]
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct MaxHitPoints(pub i32);
 
-#[derive(Debug, Component)]
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct HitPoints(pub i32);
 
-#[derive(Default, Debug,Component)]
+#[derive(Default, Debug, Clone, Component, Serialize, Deserialize)]
 pub struct Defense(pub i32);
 
-#[derive(Default, Debug, Component)]
+#[derive(Default, Debug, Clone, Component, Serialize, Deserialize)]
 pub struct Strength(pub i32);
 
-#[derive(Default, Debug, Component)]
+/// Dice rolled for damage when attacking. Serialized as a dice string, ie: `"2d6+1"`.
+#[derive(Default, Debug, Clone, Component)]
 pub struct AttackDice(pub DiceType);
 
+impl AttackDice {
+    /// Parse a dice string of the form `"1d6"`, `"2d4+1"` or `"3d8-2"`.
+    pub fn parse(dice: &str) -> Result<Self, String> {
+        // The parser will happily skip over anything it doesn't recognize
+        let valid = !dice.is_empty() && dice.chars().all(|c| c.is_ascii_digit() || "d+- ".contains(c));
+        match parse_dice_string(dice) {
+            Ok(parsed) if valid && parsed.n_dice > 0 && parsed.die_type > 0 => Ok(AttackDice(parsed)),
+            _ => Err(format!("'{}' is not a valid dice string, expected something like \"1d6\" or \"2d4+1\"", dice)),
+        }
+    }
+}
+
+impl std::fmt::Display for AttackDice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let d = &self.0;
+        match d.bonus {
+            0 => write!(f, "{}d{}", d.n_dice, d.die_type),
+            b if b > 0 => write!(f, "{}d{}+{}", d.n_dice, d.die_type, b),
+            b => write!(f, "{}d{}{}", d.n_dice, d.die_type, b),
+        }
+    }
+}
+
+impl Serialize for AttackDice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AttackDice {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let dice = String::deserialize(deserializer)?;
+        AttackDice::parse(&dice).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, Bundle)]
 pub struct CombatantBundle {
     pub hp: HitPoints,
@@ -47,6 +85,7 @@ pub struct CombatantBundle {
 
 pub enum ActorEffect {
     Heal(i32),
+    /// Rolled damage, before the actor's [Strength] and the target's [Defense] are applied.
     Damage(i32),
 }
 
@@ -62,7 +101,7 @@ pub struct ActorKilledEvent {
 
 fn resolve_target_events(
     q_names: Query<&Name>,
-    q_attack: Query<&mut Strength>,
+    q_attack: Query<&Strength>,
     mut q_defend: Query<(&mut HitPoints, &MaxHitPoints, &Defense)>,
     mut log: ResMut<PrintLog>,
     mut target_events: EventReader<TargetEvent>,
@@ -80,17 +119,19 @@ fn resolve_target_events(
                     hp.0 += amount;
                                   
                     // TODO: Move this into ui? No reason to handle it here, would make it simpler + cleaner
-                    if let Ok(actor_name) = q_names.get(actor) {
-                        if let Ok(target_name) = q_names.get(tar) {
-                            log.push(format!("{} heals {} for {} damage.", actor_name.as_str(), target_name.as_str(), amount));
+                    if let Ok(target_name) = q_names.get(tar) {
+                        if actor == tar {
+                            log.push(format!("{} recovers {} hp.", target_name.as_str(), amount));
+                        } else if let Ok(actor_name) = q_names.get(actor) {
+                            log.push(format!("{} heals {} for {} hp.", actor_name.as_str(), target_name.as_str(), amount));
                         }
                     }
                 }
             },
             ActorEffect::Damage(amount) => {
-                if let Ok(_attack) = q_attack.get(actor) {
+                if let Ok(strength) = q_attack.get(actor) {
                     if let Ok((mut hp, _, def)) = q_defend.get_mut(tar) {
-                        let amount = amount - def.0;
+                        let amount = damage(amount, strength, def);
 
                         if amount <= 0 {
                             continue;
@@ -111,6 +152,21 @@ fn resolve_target_events(
     }
 }
 
+/// Damage dealt by a hit: the roll plus the attacker's strength, minus the defender's defense.
+pub fn damage(roll: i32, strength: &Strength, defense: &Defense) -> i32 {
+    roll + strength.0 - defense.0
+}
+
+/// Apply the [TileEffect] of a tile an actor just moved onto. Only moves
+/// trigger effects, actors that are spawned, loaded or arrive from another
+/// level on a tile aren't affected by it.
+pub fn enter_tile(tile: MapTile, name: &Name, hp: &mut HitPoints, log: &mut PrintLog) {
+    if let Some(TileEffect::Burn(amount)) = tile.properties().on_enter {
+        hp.0 -= amount;
+        log.push(format!("{} is burned for {} damage.", name.as_str(), amount));
+    }
+}
+
 fn death_system(
     mut commands: Commands,
     mut log: ResMut<PrintLog>,
@@ -133,4 +189,15 @@ fn death_system(
             log.push(format!("{} was killed!", name.as_str()));
         }
     } 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strength_adds_to_damage() {
+        assert_eq!(5, damage(4, &Strength(3), &Defense(2)));
+        assert!(damage(1, &Strength(0), &Defense(2)) < 0);
+    }
+}
@@ -1,46 +1,441 @@
-use serde::Deserialize;
-use std::ops::Range;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fs::read_to_string, ops::Range, path::{Path, PathBuf}};
 
+use ron::from_str;
 
-#[derive(Debug, Deserialize)]
+pub const MAP_SETTINGS_FILE_NAME: &str = "map_settings.ron";
+
+/// Which algorithm lays out the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MapGenAlgorithm {
+    /// Randomly placed rooms, each joined to the one placed before it.
+    Rooms,
+    /// Binary space partitioning. Rooms fill the map evenly and are joined to their neighbours.
+    Bsp,
+    /// Open caverns grown with cellular automata.
+    Caves,
+    /// Winding tunnels carved by walkers stumbling around the map, see [WalkSettings].
+    DrunkardsWalk,
+    /// Diffusion limited aggregation. Walkers start anywhere and wander until they reach floor.
+    DlaWalkInwards,
+    /// Diffusion limited aggregation. Walkers start on floor and wander until they reach a wall.
+    DlaWalkOutwards,
+    /// Diffusion limited aggregation. Walkers head straight for the center of the map.
+    DlaCentralAttractor,
+}
+
+/// Where drunkards start their walk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalkerSpawn {
+    /// Every walker starts in the center of the map.
+    Center,
+    /// Walkers start on a random floor tile.
+    RandomFloor,
+}
+
+/// Settings for the random walk algorithms.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalkSettings {
+    /// Walkers are spawned until this percentage of the map is floor.
+    pub floor_percent: f32,
+    /// How many steps a walker takes before it gives up.
+    pub lifetime: u32,
+    /// Only used by `DrunkardsWalk` and `DlaWalkOutwards`.
+    pub spawn: WalkerSpawn,
+}
+
+impl Default for WalkSettings {
+    fn default() -> Self {
+        Self {
+            floor_percent: 40.0,
+            lifetime: 400,
+            spawn: WalkerSpawn::Center,
+        }
+    }
+}
+
+/// Where the player starts on a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StartPosition {
+    /// The first room the algorithm made.
+    FirstRoom,
+    /// The room closest to the center of the map.
+    Center,
+    /// Any room.
+    RandomRoom,
+}
+
+/// Which way a map is mirrored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Symmetry {
+    /// The left half is copied onto the right.
+    Horizontal,
+    /// The bottom half is copied onto the top.
+    Vertical,
+    /// The bottom left quarter is copied onto the other three.
+    Both,
+}
+
+/// A step run on the map after the [MapGenAlgorithm] lays it out, in the order listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BuildStep {
+    /// Fill in floor that can't be reached from the start, or from the
+    /// largest open area if the start hasn't been picked yet.
+    CullUnreachable,
+    /// Mirror the map, joining the halves in the middle.
+    Symmetry(Symmetry),
+    /// Pick the room the player starts in. Defaults to the first room if
+    /// no step picks one.
+    Start(StartPosition),
+    /// Stamp vaults from `vaults.ron` where they fit.
+    Vaults,
+    /// Put closed doors in some of the gaps where tunnels pass through the
+    /// walls around rooms.
+    Doors,
+    /// Scatter patches of grass, rubble, water and lava over the rooms,
+    /// leaving the player's room clear.
+    Terrain,
+    /// Place the down stairs in the last room. Below the first level the
+    /// up stairs are placed at the start.
+    Stairs,
+    /// Pick the monsters and items for each room.
+    Spawn,
+}
+
+/// Changes how levels are generated from a given depth down.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelTheme {
+    pub min_depth: u32,
+    pub algorithm: MapGenAlgorithm,
+    /// Replaces the settings' steps for this theme.
+    #[serde(default)]
+    pub steps: Option<Vec<BuildStep>>,
+}
+
+impl Default for MapGenAlgorithm {
+    fn default() -> Self {
+        Self::Rooms
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MapGenSettings {
     pub seed: u64,
+    #[serde(default)]
+    pub algorithm: MapGenAlgorithm,
     pub iterations: u32,
     pub map_size: [u32;2],
     pub room_size: Range<u32>,
     pub monsters_per_room: Range<u32>,
-    //pub items_per_room: Range<u32>,
+    #[serde(default = "default_items_per_room")]
+    pub items_per_room: Range<u32>,
+    /// Overrides `algorithm` for deeper levels.
+    #[serde(default)]
+    pub themes: Vec<LevelTheme>,
+    #[serde(default)]
+    pub walk: WalkSettings,
+    /// Run on every level after the algorithm lays out the map.
+    #[serde(default = "default_steps")]
+    pub steps: Vec<BuildStep>,
+}
+
+fn default_items_per_room() -> Range<u32> {
+    0..2
 }
 
+fn default_steps() -> Vec<BuildStep> {
+    vec![
+        BuildStep::Start(StartPosition::FirstRoom),
+        BuildStep::Vaults,
+        BuildStep::Doors,
+        BuildStep::Terrain,
+        BuildStep::Stairs,
+        BuildStep::Spawn,
+    ]
+}
+
+/// Used when the settings file can't be loaded, and by tests. The map is
+/// smaller than the one in assets/map_settings.ron to keep the generator
+/// tests quick.
 impl Default for MapGenSettings {
     fn default() -> Self {
         Self {
             seed: 5,
+            algorithm: MapGenAlgorithm::default(),
             iterations: 15,
             map_size: [80, 40],
             room_size: 3..15,
             monsters_per_room: 0..4,
-            //items_per_room: 0..2,
+            items_per_room: default_items_per_room(),
+            themes: Vec::new(),
+            walk: WalkSettings::default(),
+            steps: default_steps(),
         }
     }
 }
 
-// pub fn try_get_map_settings() -> Result<MapGenSettings, String> {
-//     let result = read_to_string(format!(
-//         "{}/assets/{}",
-//         env!("CARGO_MANIFEST_DIR"),
-//         MAP_SETTINGS_FILE_NAME
-//     ));
+impl MapGenSettings {
+    /// The algorithm used for the given depth: the theme with the deepest
+    /// `min_depth` that applies, or `algorithm` if none do.
+    pub fn algorithm_at(&self, depth: u32) -> MapGenAlgorithm {
+        self.theme_at(depth).map_or(self.algorithm, |t| t.algorithm)
+    }
 
-//     let file_string = match result {
-//         Ok(file_string) => file_string,
-//         Err(_) => return Err(format!("Error reading {}", MAP_SETTINGS_FILE_NAME)),
-//     };
+    /// The build steps used for the given depth: the steps of the theme
+    /// from [MapGenSettings::algorithm_at] if it has any, or `steps`.
+    pub fn steps_at(&self, depth: u32) -> &[BuildStep] {
+        self.theme_at(depth)
+            .and_then(|t| t.steps.as_deref())
+            .unwrap_or(&self.steps)
+    }
 
-//     let settings: MapGenSettings = match from_str(file_string.as_str()) {
-//         Ok(settings) => settings,
-//         Err(e) => return Err(format!("Error parsing {}: {}", MAP_SETTINGS_FILE_NAME, e)),
-//     };
+    fn theme_at(&self, depth: u32) -> Option<&LevelTheme> {
+        self.themes
+            .iter()
+            .filter(|t| t.min_depth <= depth)
+            .max_by_key(|t| t.min_depth)
+    }
 
-//     Ok(settings)
-// }
+    /// Check for values the map generator can't work with.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.iterations == 0 {
+            return Err("iterations must be at least 1".to_string());
+        }
+        if self.room_size.is_empty() {
+            return Err(format!(
+                "room_size {:?} is empty, end must be greater than start",
+                self.room_size
+            ));
+        }
+        if self.room_size.start == 0 {
+            return Err("room_size must start at 1 or more".to_string());
+        }
+        if self.monsters_per_room.is_empty() {
+            return Err(format!(
+                "monsters_per_room {:?} is empty, end must be greater than start",
+                self.monsters_per_room
+            ));
+        }
+        if self.items_per_room.is_empty() {
+            return Err(format!(
+                "items_per_room {:?} is empty, end must be greater than start",
+                self.items_per_room
+            ));
+        }
+
+        if !(self.walk.floor_percent > 0.0 && self.walk.floor_percent <= 90.0) {
+            return Err(format!(
+                "walk floor_percent is {}, it must be more than 0 and at most 90",
+                self.walk.floor_percent
+            ));
+        }
+        if self.walk.lifetime == 0 {
+            return Err("walk lifetime must be at least 1".to_string());
+        }
+
+        let step_lists = std::iter::once(&self.steps).chain(self.themes.iter().filter_map(|t| t.steps.as_ref()));
+        for steps in step_lists {
+            // Mirroring would cover up anything placed on the map
+            let placing = steps.iter().position(|s| {
+                matches!(s, BuildStep::Start(_) | BuildStep::Vaults | BuildStep::Stairs | BuildStep::Spawn)
+            });
+            let mirror = steps.iter().rposition(|s| matches!(s, BuildStep::Symmetry(_)));
+            if let (Some(placing), Some(mirror)) = (placing, mirror) {
+                if mirror > placing {
+                    return Err(format!(
+                        "{:?} must come before {:?} in the build steps",
+                        steps[mirror], steps[placing]
+                    ));
+                }
+            }
+        }
+
+        // Rooms are placed at least 2 tiles from the left/bottom edge and
+        // 2 tiles from the right/top edge.
+        let largest_room = self.room_size.end - 1;
+        for (axis, size) in ["width", "height"].iter().zip(self.map_size) {
+            if largest_room + 5 > size {
+                return Err(format!(
+                    "room_size {:?} is too large for a map {} of {}, rooms can be at most {} tiles",
+                    self.room_size,
+                    axis,
+                    size,
+                    size.saturating_sub(5)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Path to a file in the assets folder.
+///
+/// Uses `CARGO_MANIFEST_DIR` if it's set (running through cargo), otherwise
+/// the folder containing the executable.
+pub fn asset_path(file_name: &str) -> PathBuf {
+    let root = match std::env::var("CARGO_MANIFEST_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|p| p.to_path_buf()))
+            .unwrap_or_default(),
+    };
+    root.join("assets").join(file_name)
+}
+
+/// Read and parse a RON file from the assets folder.
+pub fn load_asset<T: DeserializeOwned>(file_name: &str) -> Result<T, String> {
+    let result = read_to_string(asset_path(file_name));
+
+    let file_string = match result {
+        Ok(file_string) => file_string,
+        Err(e) => return Err(format!("Error reading {}: {}", file_name, e)),
+    };
+
+    match from_str(file_string.as_str()) {
+        Ok(asset) => Ok(asset),
+        Err(e) => Err(format!("Error parsing {}: {}", file_name, e)),
+    }
+}
+
+pub fn try_get_map_settings() -> Result<MapGenSettings, String> {
+    let settings: MapGenSettings = load_asset(MAP_SETTINGS_FILE_NAME)?;
+
+    if let Err(e) = settings.validate() {
+        return Err(format!("Invalid settings in {}: {}", MAP_SETTINGS_FILE_NAME, e));
+    }
+
+    Ok(settings)
+}
+
+/// Read and validate map settings from a file outside the assets folder,
+/// ie: one given on the command line.
+pub fn load_map_settings(path: &Path) -> Result<MapGenSettings, String> {
+    let file_string = match read_to_string(path) {
+        Ok(file_string) => file_string,
+        Err(e) => return Err(format!("Error reading {}: {}", path.display(), e)),
+    };
+
+    let settings: MapGenSettings = match from_str(file_string.as_str()) {
+        Ok(settings) => settings,
+        Err(e) => return Err(format!("Error parsing {}: {}", path.display(), e)),
+    };
+
+    if let Err(e) = settings.validate() {
+        return Err(format!("Invalid settings in {}: {}", path.display(), e));
+    }
+
+    Ok(settings)
+}
+
+/// Read the map settings from the assets folder, falling back to the defaults
+/// if they can't be loaded.
+pub fn get_map_settings() -> MapGenSettings {
+    match try_get_map_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}. Using default map settings.", e);
+            MapGenSettings::default()
+        }
+    }
+}
+
+/// The value following `name` in the command line arguments, ie: `--seed 5`.
+pub fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
+
+/// Whether `name` was passed as a command line argument, ie: `--headless`.
+pub fn has_arg(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asset_settings_are_valid() {
+        try_get_map_settings().unwrap();
+    }
+
+    #[test]
+    fn default_settings_are_valid() {
+        MapGenSettings::default().validate().unwrap();
+    }
+
+    #[test]
+    fn empty_ranges() {
+        let settings = MapGenSettings {
+            room_size: 5..5,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = MapGenSettings {
+            monsters_per_room: 3..1,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn room_larger_than_map() {
+        let settings = MapGenSettings {
+            map_size: [20, 20],
+            room_size: 3..17,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = MapGenSettings {
+            map_size: [20, 20],
+            room_size: 3..16,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn themes_override_algorithm() {
+        let settings = MapGenSettings {
+            algorithm: MapGenAlgorithm::Bsp,
+            themes: vec![
+                LevelTheme { min_depth: 5, algorithm: MapGenAlgorithm::Rooms, steps: None },
+                LevelTheme {
+                    min_depth: 3,
+                    algorithm: MapGenAlgorithm::Caves,
+                    steps: Some(vec![BuildStep::CullUnreachable, BuildStep::Spawn]),
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(MapGenAlgorithm::Bsp, settings.algorithm_at(2));
+        assert_eq!(MapGenAlgorithm::Caves, settings.algorithm_at(3));
+        assert_eq!(MapGenAlgorithm::Rooms, settings.algorithm_at(7));
+
+        assert_eq!(settings.steps, settings.steps_at(2));
+        assert_eq!(2, settings.steps_at(4).len());
+        assert_eq!(settings.steps, settings.steps_at(7));
+    }
+
+    #[test]
+    fn symmetry_before_placing() {
+        let mut settings = MapGenSettings::default();
+        settings.steps.push(BuildStep::Symmetry(Symmetry::Both));
+        assert!(settings.validate().is_err());
+
+        settings.steps.insert(0, BuildStep::Symmetry(Symmetry::Both));
+        settings.steps.pop();
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn parse_error() {
+        let result: Result<MapGenSettings, _> = from_str("MapGenSettings( seed: 5 )");
+        assert!(result.is_err());
+    }
+}
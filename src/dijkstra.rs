@@ -0,0 +1,287 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{prelude::*, utils::HashMap};
+use sark_grids::Grid;
+
+use crate::{ai::MONSTER_AI_SYSTEM_LABEL, map::Map, movement::Position, player::Player};
+
+/// Label for the system rebuilding the [PlayerMaps]. Occurs in [CoreStage::Update].
+pub const PLAYER_MAPS_SYSTEM_LABEL: &str = "update_player_maps";
+
+/// The value of tiles that can't reach any goal.
+pub const UNREACHABLE: i32 = i32::MAX;
+
+/// Flee maps scale distances by this much, as a fraction. Above 1 and fleeing
+/// actors will run past the player towards open space instead of into the
+/// nearest dead end.
+const FLEE_SCALE: (i32, i32) = (6, 5);
+
+/// Goal maps kept at once before they're all thrown out, see [GoalMaps].
+const MAX_GOAL_MAPS: usize = 32;
+
+/// The eight directions an actor can step in.
+pub(crate) const DIRECTIONS: [[i32; 2]; 8] = [[-1, -1], [0, -1], [1, -1], [-1, 0], [1, 0], [-1, 1], [0, 1], [1, 1]];
+
+/// Keeps the [PlayerMaps] and [GoalMaps] up to date for monster AI.
+pub struct DijkstraPlugin;
+
+impl Plugin for DijkstraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerMaps>()
+        .init_resource::<GoalMaps>()
+        .add_system(update_player_maps
+            .label(PLAYER_MAPS_SYSTEM_LABEL)
+            .before(MONSTER_AI_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// The cost of the cheapest path from every tile to the nearest of a set of
+/// goals. Actors follow it by stepping "downhill", see [DijkstraMap::next_step].
+///
+/// Unlike a single path from [sark_pathfinding], one map can be shared by
+/// every actor heading for the same goals.
+#[derive(Debug, Clone)]
+pub struct DijkstraMap {
+    values: Grid<i32>,
+}
+
+impl Default for DijkstraMap {
+    fn default() -> Self {
+        Self {
+            values: Grid::default([0, 0]),
+        }
+    }
+}
+
+impl DijkstraMap {
+    /// Build a map leading to the given goals. Each goal starts at its own
+    /// value, lower values are more attractive.
+    ///
+    /// `cost` is what it costs to step onto a tile, or `None` for tiles
+    /// that can't be walked on.
+    pub fn new(
+        size: UVec2,
+        goals: impl IntoIterator<Item = (IVec2, i32)>,
+        cost: impl Fn(IVec2) -> Option<i32>,
+    ) -> Self {
+        let mut map = Self {
+            values: Grid::default(size),
+        };
+        for value in map.values.iter_mut() {
+            *value = UNREACHABLE;
+        }
+
+        let mut open = BinaryHeap::new();
+        for (p, value) in goals {
+            if map.values.in_bounds(p) && cost(p).is_some() && value < map.values[p] {
+                map.values[p] = value;
+                open.push(Reverse((value, p.x, p.y)));
+            }
+        }
+
+        while let Some(Reverse((value, x, y))) = open.pop() {
+            let p = IVec2::new(x, y);
+            // Already reached more cheaply
+            if value > map.values[p] {
+                continue;
+            }
+            for dir in DIRECTIONS {
+                let next = p + IVec2::from(dir);
+                if !map.values.in_bounds(next) {
+                    continue;
+                }
+                let next_value = match cost(next) {
+                    Some(cost) => value.saturating_add(cost),
+                    None => continue,
+                };
+                if next_value < map.values[next] {
+                    map.values[next] = next_value;
+                    open.push(Reverse((next_value, next.x, next.y)));
+                }
+            }
+        }
+        map
+    }
+
+    /// A map leading away from this map's goals, for fleeing.
+    pub fn inverted(&self, cost: impl Fn(IVec2) -> Option<i32>) -> Self {
+        let (num, den) = FLEE_SCALE;
+        let goals: Vec<(IVec2, i32)> = self.values.iter().enumerate()
+            .filter(|(_, value)| **value != UNREACHABLE)
+            .map(|(i, value)| (self.values.index_to_pos(i), -(value.saturating_mul(num) / den)))
+            .collect();
+        Self::new(self.values.size(), goals, cost)
+    }
+
+    /// The value of a tile, `None` if it can't reach a goal.
+    pub fn value(&self, p: IVec2) -> Option<i32> {
+        if !self.values.in_bounds(p) || self.values[p] == UNREACHABLE {
+            return None;
+        }
+        Some(self.values[p])
+    }
+
+    /// The lowest neighbour of `pos` that isn't `blocked`, if any are lower
+    /// than `pos` itself.
+    pub fn next_step(&self, pos: IVec2, blocked: impl Fn(IVec2) -> bool) -> Option<IVec2> {
+        let current = self.value(pos)?;
+        DIRECTIONS.iter()
+            .map(|dir| pos + IVec2::from(*dir))
+            .filter_map(|p| Some((p, self.value(p)?)))
+            .filter(|(p, value)| *value < current && !blocked(*p))
+            .min_by_key(|(_, value)| *value)
+            .map(|(p, _)| p)
+    }
+}
+
+/// The cost of stepping onto each tile of the map, see [crate::map::TileProperties::move_cost].
+/// Closed doors can be walked through once they're opened.
+pub fn tile_cost(map: &Map) -> impl Fn(IVec2) -> Option<i32> + '_ {
+    move |p| {
+        let tile = map.0[p];
+        if tile.is_passable() {
+            Some(tile.properties().move_cost)
+        } else {
+            None
+        }
+    }
+}
+
+/// Maps leading towards and away from the player, shared by every monster.
+/// Actors aren't obstacles on these maps, they move too often.
+#[derive(Default)]
+pub struct PlayerMaps {
+    pub approach: DijkstraMap,
+    pub flee: DijkstraMap,
+}
+
+/// Maps leading to single tiles monsters head for other than the player,
+/// like where they last saw the player or a patrol goal. Monsters heading
+/// for the same tile share its map.
+///
+/// Maps are kept until the map changes, or until there are too many of them.
+#[derive(Default)]
+pub struct GoalMaps {
+    maps: HashMap<IVec2, DijkstraMap>,
+}
+
+impl GoalMaps {
+    /// The map leading to `goal`, built if nobody has headed there yet.
+    pub fn get(&mut self, map: &Map, goal: IVec2) -> &DijkstraMap {
+        if self.maps.len() >= MAX_GOAL_MAPS && !self.maps.contains_key(&goal) {
+            self.maps.clear();
+        }
+        self.maps.entry(goal)
+            .or_insert_with(|| DijkstraMap::new(map.0.size(), [(goal, 0)], tile_cost(map)))
+    }
+
+    pub fn clear(&mut self) {
+        self.maps.clear();
+    }
+}
+
+fn update_player_maps(
+    mut maps: ResMut<PlayerMaps>,
+    mut goal_maps: ResMut<GoalMaps>,
+    q_player: Query<(&Position, ChangeTrackers<Position>), With<Player>>,
+    q_map: Query<(&Map, ChangeTrackers<Map>)>,
+) {
+    let ((pos, player_changes), (map, map_changes)) = match (q_player.get_single(), q_map.get_single()) {
+        (Ok(player), Ok(map)) => (player, map),
+        _ => return,
+    };
+
+    if map_changes.is_changed() {
+        goal_maps.clear();
+    }
+
+    // Only rebuilt when the player moves or the map changes
+    if !player_changes.is_changed() && !map_changes.is_changed() {
+        return;
+    }
+
+    let cost = tile_cost(map);
+    let approach = DijkstraMap::new(map.0.size(), [(pos.0, 0)], &cost);
+    maps.flee = approach.inverted(&cost);
+    maps.approach = approach;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::map::MapTile;
+
+    use super::*;
+
+    fn open_map(size: [u32; 2]) -> Map {
+        let mut map = Map(Grid::default(size));
+        for tile in map.0.iter_mut() {
+            *tile = MapTile::Floor;
+        }
+        map
+    }
+
+    #[test]
+    fn distance_to_nearest_goal() {
+        let map = open_map([10, 3]);
+        let cost = |_| Some(1);
+        let dijkstra = DijkstraMap::new(map.0.size(), [(IVec2::new(0, 1), 0), (IVec2::new(9, 1), 0)], cost);
+
+        assert_eq!(Some(0), dijkstra.value(IVec2::new(0, 1)));
+        // Diagonal steps cost the same as straight ones
+        assert_eq!(Some(2), dijkstra.value(IVec2::new(2, 0)));
+        assert_eq!(Some(1), dijkstra.value(IVec2::new(8, 2)));
+        assert_eq!(None, dijkstra.value(IVec2::new(10, 1)));
+    }
+
+    #[test]
+    fn walls_and_weighted_tiles() {
+        let mut map = open_map([5, 1]);
+        map.0[[2, 0]] = MapTile::ShallowWater;
+        let dijkstra = DijkstraMap::new(map.0.size(), [(IVec2::ZERO, 0)], tile_cost(&map));
+
+        let water = MapTile::ShallowWater.properties().move_cost;
+        let floor = MapTile::Floor.properties().move_cost;
+        assert_eq!(Some(floor + water + floor), dijkstra.value(IVec2::new(3, 0)));
+
+        map.0[[2, 0]] = MapTile::Wall;
+        let dijkstra = DijkstraMap::new(map.0.size(), [(IVec2::ZERO, 0)], tile_cost(&map));
+        assert_eq!(None, dijkstra.value(IVec2::new(3, 0)));
+    }
+
+    #[test]
+    fn step_downhill_around_blocked_tiles() {
+        let map = open_map([5, 3]);
+        let dijkstra = DijkstraMap::new(map.0.size(), [(IVec2::new(4, 1), 0)], tile_cost(&map));
+
+        let pos = IVec2::new(1, 1);
+        let step = dijkstra.next_step(pos, |p| p == IVec2::new(2, 1)).unwrap();
+        assert_eq!(2, step.x);
+        assert_ne!(IVec2::new(2, 1), step);
+        // Sideways steps don't get any closer
+        assert_eq!(None, dijkstra.next_step(pos, |p| p.x == 2));
+        // Nowhere lower to go at the goal
+        assert_eq!(None, dijkstra.next_step(IVec2::new(4, 1), |_| false));
+    }
+
+    #[test]
+    fn goal_maps_lead_to_their_goal() {
+        let map = open_map([6, 3]);
+        let mut goal_maps = GoalMaps::default();
+        let goal = IVec2::new(5, 1);
+
+        assert_eq!(Some(0), goal_maps.get(&map, goal).value(goal));
+        assert_eq!(Some(IVec2::new(4, 1)), goal_maps.get(&map, goal).next_step(IVec2::new(3, 1), |_| false));
+    }
+
+    #[test]
+    fn flee_map_leads_away() {
+        let map = open_map([9, 1]);
+        let cost = tile_cost(&map);
+        let approach = DijkstraMap::new(map.0.size(), [(IVec2::new(2, 0), 0)], &cost);
+        let flee = approach.inverted(&cost);
+
+        assert_eq!(Some(IVec2::new(4, 0)), flee.next_step(IVec2::new(3, 0), |_| false));
+        assert_eq!(Some(IVec2::new(0, 0)), flee.next_step(IVec2::new(1, 0), |_| false));
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action::RESOLVE_ACTIONS_SYSTEM_LABEL,
+    ai::MonsterAi,
+    config::MapGenSettings,
+    map::{level_rng, Map, MapGenAssets, MapGenEntities, MapGenerator, MapTile},
+    map_gen::Vaults,
+    monster::{Monster, MonsterTemplates},
+    inventory::{Item, ItemTemplates},
+    movement::Position,
+    player::Player,
+    rng::RunSeed,
+    save::{ActorComponents, ItemData, MapData, MonsterData},
+    turn_system::TurnQueue,
+    ui::PrintLog,
+    visibility::MapMemory,
+    visualizer::MapGenVisualizer,
+};
+
+/// Label for the system moving the player between levels. Occurs in [CoreStage::Update].
+pub const CHANGE_LEVEL_SYSTEM_LABEL: &str = "change_level";
+
+pub struct DungeonPlugin;
+
+impl Plugin for DungeonPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Dungeon>()
+        .add_event::<ChangeLevelEvent>()
+        .add_system(change_level
+            .label(CHANGE_LEVEL_SYSTEM_LABEL)
+            .after(RESOLVE_ACTIONS_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// The levels of the dungeon.
+pub struct Dungeon {
+    /// The player's current level, starting at 1.
+    pub depth: u32,
+    /// Levels the player has left, by depth. They're restored as they were
+    /// when the player returns.
+    pub levels: BTreeMap<u32, LevelData>,
+}
+
+impl Default for Dungeon {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            levels: Default::default(),
+        }
+    }
+}
+
+/// A level the player isn't currently on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelData {
+    pub map: MapData,
+    pub memory: MapMemory,
+    pub monsters: Vec<MonsterData>,
+    /// Items lying on the map.
+    #[serde(default)]
+    pub items: Vec<ItemData>,
+}
+
+/// Sent when the player takes the stairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeLevelEvent {
+    Descend,
+    Ascend,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn change_level(
+    mut commands: Commands,
+    mut evt_level: EventReader<ChangeLevelEvent>,
+    mut dungeon: ResMut<Dungeon>,
+    mut log: ResMut<PrintLog>,
+    settings: Res<MapGenSettings>,
+    seed: Res<RunSeed>,
+    monsters: Res<MonsterTemplates>,
+    items: Res<ItemTemplates>,
+    vaults: Res<Vaults>,
+    visualizer: Option<Res<MapGenVisualizer>>,
+    q_map: Query<(Entity, &Map)>,
+    q_monsters: Query<(Entity, ActorComponents, &MonsterAi), With<Monster>>,
+    q_items: Query<(Entity, &Name, &Position), With<Item>>,
+    q_player: Query<(Entity, &MapMemory), With<Player>>,
+    queue: Res<TurnQueue>,
+) {
+    let ev = match evt_level.iter().next() {
+        Some(ev) => *ev,
+        None => return,
+    };
+
+    let (player, memory) = match q_player.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    let (map_entity, map) = match q_map.get_single() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
+
+    if ev == ChangeLevelEvent::Ascend && dungeon.depth <= 1 {
+        return;
+    }
+
+    // Store the level we're leaving
+    let level = LevelData {
+        map: MapData::from(map),
+        memory: memory.clone(),
+        monsters: q_monsters.iter()
+            .map(|(entity, actor, ai)| MonsterData::from_components(actor, queue.ticks_until(entity), ai))
+            .collect(),
+        items: q_items.iter().map(|(_, name, pos)| ItemData::new(name, pos)).collect(),
+    };
+    let depth = dungeon.depth;
+    dungeon.levels.insert(depth, level);
+
+    commands.entity(map_entity).despawn();
+    for (entity, ..) in q_monsters.iter() {
+        commands.entity(entity).despawn();
+    }
+    // Items in the player's inventory have no position and come along
+    for (entity, _, _) in q_items.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let (depth, arrive_on) = match ev {
+        ChangeLevelEvent::Descend => (depth + 1, MapTile::UpStairs),
+        ChangeLevelEvent::Ascend => (depth - 1, MapTile::DownStairs),
+    };
+    dungeon.depth = depth;
+
+    match dungeon.levels.remove(&depth) {
+        Some(level) => {
+            let map = Map::from(&level.map);
+
+            // Arrive on the stairs leading back to where we came from
+            if let Some(i) = map.0.iter().position(|t| *t == arrive_on) {
+                let pos = map.0.index_to_pos(i);
+                commands.entity(player).insert(Position::from(pos));
+            }
+            commands.entity(player).insert(level.memory);
+
+            for monster in level.monsters.iter() {
+                let mut entity = commands.spawn_bundle(monster.monster_bundle());
+                monster.actor.insert_turn_state(&mut entity);
+            }
+            for item in level.items.iter() {
+                item.spawn(&mut commands, &items);
+            }
+            commands.spawn().insert(map);
+        },
+        None => {
+            commands.entity(player).insert(MapMemory::default());
+
+            let entities = MapGenEntities {
+                player: Some(player),
+            };
+            let assets = MapGenAssets {
+                monsters: &monsters,
+                items: &items,
+                vaults: &vaults,
+            };
+            let record = visualizer.is_some();
+            MapGenerator::build(&mut commands, &settings, level_rng(seed.0, depth), entities, &assets, depth, record);
+        },
+    }
+
+    match ev {
+        ChangeLevelEvent::Descend => log.push(format!("You descend to depth {}.", depth)),
+        ChangeLevelEvent::Ascend => log.push(format!("You climb back up to depth {}.", depth)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sark_grids::Grid;
+
+    use crate::{combat::HitPoints, test_util::{map_app, send}};
+
+    use super::*;
+
+    /// A corridor with stairs at either end.
+    fn level() -> Map {
+        let mut map = Map(Grid::default([6, 3]));
+        for x in 1..5 {
+            map.0[[x, 1]] = MapTile::Floor;
+        }
+        map.0[[1, 1]] = MapTile::UpStairs;
+        map.0[[4, 1]] = MapTile::DownStairs;
+        map
+    }
+
+    #[test]
+    fn levels_are_restored_on_return() {
+        let mut app = map_app(level());
+        app.insert_resource(MapGenSettings::default())
+        .insert_resource(RunSeed(0))
+        .init_resource::<MonsterTemplates>()
+        .init_resource::<ItemTemplates>()
+        .init_resource::<Vaults>()
+        .init_resource::<Dungeon>()
+        .init_resource::<TurnQueue>()
+        .add_event::<ChangeLevelEvent>()
+        .add_system(change_level);
+
+        // Already visited, so it isn't generated
+        app.world.resource_mut::<Dungeon>().levels.insert(2, LevelData {
+            map: MapData::from(&level()),
+            memory: MapMemory::default(),
+            monsters: Vec::new(),
+            items: Vec::new(),
+        });
+
+        let memory = MapMemory(vec![true; level().0.len()]);
+        let player = app.world.spawn()
+            .insert_bundle((Player, Position(IVec2::new(4, 1)), memory))
+            .id();
+        let mut goblin = MonsterTemplates::default().get("Goblin").unwrap().bundle();
+        goblin.movable.position = Position(IVec2::new(2, 1));
+        goblin.combatant_bundle.hp.0 = 4;
+        app.world.spawn().insert_bundle(goblin);
+        let potion = ItemTemplates::default().get("Healing Potion").unwrap().bundle();
+        app.world.spawn().insert_bundle(potion).insert(Position(IVec2::new(3, 1)));
+
+        send(&mut app, ChangeLevelEvent::Descend);
+        assert_eq!(2, app.world.resource::<Dungeon>().depth);
+        assert_eq!(IVec2::new(1, 1), app.world.get::<Position>(player).unwrap().0);
+        assert!(app.world.get::<MapMemory>(player).unwrap().0.is_empty());
+        assert_eq!(0, app.world.query::<&Monster>().iter(&app.world).count());
+        assert_eq!(0, app.world.query::<&Item>().iter(&app.world).count());
+
+        send(&mut app, ChangeLevelEvent::Ascend);
+        let dungeon = app.world.resource::<Dungeon>();
+        assert_eq!(1, dungeon.depth);
+        assert!(dungeon.levels.contains_key(&2));
+        assert!(!dungeon.levels.contains_key(&1));
+        assert_eq!(IVec2::new(4, 1), app.world.get::<Position>(player).unwrap().0);
+        assert!(app.world.get::<MapMemory>(player).unwrap().0.iter().all(|seen| *seen));
+        assert_eq!(1, app.world.query::<&Map>().iter(&app.world).count());
+
+        let monsters: Vec<_> = app.world.query_filtered::<(&Name, &Position, &HitPoints), With<Monster>>()
+            .iter(&app.world)
+            .map(|(name, pos, hp)| (name.to_string(), pos.0, hp.0))
+            .collect();
+        assert_eq!(vec![("Goblin".to_string(), IVec2::new(2, 1), 4)], monsters);
+        let items: Vec<_> = app.world.query_filtered::<(&Name, &Position), With<Item>>()
+            .iter(&app.world)
+            .map(|(name, pos)| (name.to_string(), pos.0))
+            .collect();
+        assert_eq!(vec![("Healing Potion".to_string(), IVec2::new(3, 1))], items);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action::RESOLVE_ACTIONS_SYSTEM_LABEL,
+    combat::{AttackDice, CombatantBundle, Defense, Strength},
+};
+
+/// Label for the system applying equipment to an actor's stats. Occurs in [CoreStage::Update].
+pub const APPLY_EQUIPMENT_SYSTEM_LABEL: &str = "apply_equipment";
+
+pub struct EquipmentPlugin;
+
+impl Plugin for EquipmentPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(apply_equipment
+            .label(APPLY_EQUIPMENT_SYSTEM_LABEL)
+            // Stats change the same turn an item is equipped
+            .after(RESOLVE_ACTIONS_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// Where an item is worn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EquipSlot {
+    MainHand,
+    OffHand,
+    Head,
+    Body,
+    Feet,
+}
+
+impl std::fmt::Display for EquipSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EquipSlot::MainHand => "main hand",
+            EquipSlot::OffHand => "off hand",
+            EquipSlot::Head => "head",
+            EquipSlot::Body => "body",
+            EquipSlot::Feet => "feet",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An item that can be worn in an [EquipSlot].
+#[derive(Component, Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Equippable {
+    pub slot: EquipSlot,
+    /// Replaces the wearer's attack dice.
+    #[serde(default)]
+    pub attack: Option<AttackDice>,
+    /// Added to the wearer's strength.
+    #[serde(default)]
+    pub strength: i32,
+    /// Added to the wearer's defense.
+    #[serde(default)]
+    pub defense: i32,
+}
+
+/// The items an actor is wearing. Equipped items stay in the actor's inventory.
+#[derive(Component, Default, Debug)]
+pub struct Equipment {
+    pub slots: BTreeMap<EquipSlot, Entity>,
+}
+
+impl Equipment {
+    pub fn slot_of(&self, item: Entity) -> Option<EquipSlot> {
+        self.slots.iter().find(|(_, e)| **e == item).map(|(slot, _)| *slot)
+    }
+
+    /// Take off the item if it's equipped. Returns the slot it was in.
+    pub fn unequip(&mut self, item: Entity) -> Option<EquipSlot> {
+        let slot = self.slot_of(item)?;
+        self.slots.remove(&slot);
+        Some(slot)
+    }
+}
+
+/// An actor's stats without any equipment. Their [Defense], [Strength] and
+/// [AttackDice] are recomputed from these whenever their [Equipment] changes.
+#[derive(Component, Debug, Clone)]
+pub struct BaseStats {
+    pub defense: i32,
+    pub strength: i32,
+    pub attack_dice: AttackDice,
+}
+
+impl From<&CombatantBundle> for BaseStats {
+    fn from(combatant: &CombatantBundle) -> Self {
+        Self {
+            defense: combatant.defense.0,
+            strength: combatant.strength.0,
+            attack_dice: combatant.attack_dice.clone(),
+        }
+    }
+}
+
+fn apply_equipment(
+    mut q_wearers: Query<(&Equipment, &BaseStats, &mut Defense, &mut Strength, &mut AttackDice), Changed<Equipment>>,
+    q_equippable: Query<&Equippable>,
+) {
+    for (equipment, base, mut defense, mut strength, mut attack_dice) in q_wearers.iter_mut() {
+        defense.0 = base.defense;
+        strength.0 = base.strength;
+        *attack_dice = base.attack_dice.clone();
+
+        for item in equipment.slots.values() {
+            if let Ok(equippable) = q_equippable.get(*item) {
+                defense.0 += equippable.defense;
+                strength.0 += equippable.strength;
+                if let Some(attack) = &equippable.attack {
+                    *attack_dice = attack.clone();
+                }
+            }
+        }
+    }
+}
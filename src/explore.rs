@@ -0,0 +1,267 @@
+use bevy::prelude::*;
+use sark_grids::Grid;
+use sark_pathfinding::*;
+
+use crate::{
+    action::{Action, ActionEvent},
+    combat::HitPoints,
+    dijkstra::{tile_cost, DijkstraMap},
+    map::Map,
+    map_state::MapObstacles,
+    monster::Monster,
+    movement::Position,
+    player::{InputMode, Player, PLAYER_INPUT_SYSTEM_LABEL},
+    replay::ReplayPlayback,
+    turn_system::TakingATurn,
+    ui::PrintLog,
+    visibility::{MapMemory, MapView},
+};
+
+/// Label for the system walking the player towards unexplored tiles. Occurs in [CoreStage::PreUpdate].
+pub const AUTO_EXPLORE_SYSTEM_LABEL: &str = "auto_explore";
+
+/// Seconds between steps so the player can follow along.
+const EXPLORE_STEP_INTERVAL: f32 = 0.03;
+
+/// Press `O` to walk towards the closest tile the player hasn't seen yet,
+/// one turn at a time.
+///
+/// Exploring stops when a monster comes into view, when the player is hurt,
+/// when there's nothing left to explore or when any key is pressed.
+pub struct ExplorePlugin;
+
+impl Plugin for ExplorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AutoExplore>()
+        .add_system_to_stage(CoreStage::PreUpdate, auto_explore
+            .label(AUTO_EXPLORE_SYSTEM_LABEL)
+            .after(PLAYER_INPUT_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// Whether the player is auto-exploring.
+pub struct AutoExplore {
+    pub active: bool,
+    /// The player's hp when they last stepped, exploring stops if it drops.
+    hp: i32,
+    timer: Timer,
+}
+
+impl Default for AutoExplore {
+    fn default() -> Self {
+        Self {
+            active: false,
+            hp: 0,
+            timer: Timer::from_seconds(EXPLORE_STEP_INTERVAL, true),
+        }
+    }
+}
+
+impl AutoExplore {
+    fn stop(&mut self, log: &mut PrintLog, message: &str) {
+        self.active = false;
+        log.push(message.to_string());
+    }
+}
+
+/// Why [explore_step] can't take a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExploreError {
+    /// Every tile the player can reach has been seen.
+    Explored,
+    /// There's more to explore, but other actors are in the way.
+    Blocked,
+}
+
+/// The next step towards the closest reachable tile that isn't in `memory`.
+///
+/// The closest unseen tile is found with a [DijkstraMap] from `pos`, which
+/// changes with every step so unlike the [crate::dijkstra::PlayerMaps] it's
+/// built each time. The path there is found with [AStar]. Tiles that hurt to
+/// walk on, like lava, are avoided.
+pub fn explore_step(map: &Map, obstacles: &MapObstacles, memory: &MapMemory, pos: IVec2) -> Result<IVec2, ExploreError> {
+    let is_hazard = |p: IVec2| map.0[p].properties().on_enter.is_some();
+    let cost = tile_cost(map);
+    let safe_cost = |p: IVec2| cost(p).filter(|_| p == pos || !is_hazard(p));
+    let clear_cost = |p: IVec2| safe_cost(p).filter(|_| p == pos || !obstacles.0[p]);
+
+    let seen = |i: usize| memory.0.get(i).copied().unwrap_or(false);
+    let closest_unseen = |distances: DijkstraMap| {
+        (0..map.0.len())
+            .filter(|i| !seen(*i))
+            .map(|i| map.0.index_to_pos(i))
+            .filter(|p| *p != pos)
+            .filter_map(|p| Some((p, distances.value(p)?)))
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(p, _)| p)
+    };
+
+    let target = match closest_unseen(DijkstraMap::new(map.0.size(), [(pos, 0)], clear_cost)) {
+        Some(target) => target,
+        // Walls can't be moved, so if the tiles are there without actors in
+        // the way it's the actors blocking them
+        None => match closest_unseen(DijkstraMap::new(map.0.size(), [(pos, 0)], safe_cost)) {
+            Some(_) => return Err(ExploreError::Blocked),
+            None => return Err(ExploreError::Explored),
+        },
+    };
+
+    let mut blocked: PathMap2d = Grid::default(map.0.size());
+    for i in 0..map.0.len() {
+        blocked[i] = clear_cost(map.0.index_to_pos(i)).is_none();
+    }
+
+    let mut astar = AStar::new(5);
+    match astar.find_path(&blocked, pos, target) {
+        Some(path) if path.len() >= 2 => Ok(path[1]),
+        _ => Err(ExploreError::Blocked),
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn auto_explore(
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mode: Res<InputMode>,
+    replay: Option<Res<ReplayPlayback>>,
+    mut explore: ResMut<AutoExplore>,
+    q_player: Query<(Entity, &Position, &MapView, &MapMemory, &HitPoints, Option<&TakingATurn>), With<Player>>,
+    q_monsters: Query<(&Position, &Name), With<Monster>>,
+    q_map: Query<&Map>,
+    obstacles: Res<MapObstacles>,
+    mut log: ResMut<PrintLog>,
+    mut evt_action: EventWriter<ActionEvent>,
+) {
+    // Replays already have every step recorded
+    if replay.is_some() || *mode != InputMode::Game {
+        explore.active = false;
+        return;
+    }
+
+    let (player, pos, view, memory, hp, taking_turn) = match q_player.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    if !explore.active {
+        if input.just_pressed(KeyCode::O) {
+            explore.active = true;
+            explore.hp = hp.0;
+        }
+        return;
+    }
+
+    if input.get_just_pressed().next().is_some() {
+        explore.stop(&mut log, "You stop exploring.");
+        return;
+    }
+
+    if taking_turn.is_none() || !explore.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let visible = q_monsters.iter().find(|(p, _)| view.0.in_bounds(p.0) && view.0[p.0]);
+    if let Some((_, name)) = visible {
+        explore.stop(&mut log, &format!("You see a {}.", name.as_str()));
+        return;
+    }
+    if hp.0 < explore.hp {
+        explore.stop(&mut log, "You are hurt, you stop exploring.");
+        return;
+    }
+    explore.hp = hp.0;
+
+    let map = match q_map.get_single() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
+
+    let next = match explore_step(map, &obstacles, memory, pos.0) {
+        Ok(next) => next,
+        Err(ExploreError::Explored) => {
+            explore.stop(&mut log, "There's nowhere left to explore.");
+            return;
+        },
+        Err(ExploreError::Blocked) => {
+            explore.stop(&mut log, "Something is in the way.");
+            return;
+        },
+    };
+
+    let dir = (next - pos.0).into();
+    let action = if map.0[next].properties().opens_to.is_some() {
+        Action::OpenDoor(dir)
+    } else {
+        Action::Move(dir)
+    };
+    evt_action.send(ActionEvent { actor: player, action });
+}
+
+#[cfg(test)]
+mod test {
+    use sark_grids::Grid;
+
+    use crate::map::MapTile;
+
+    use super::*;
+
+    /// A corridor along the bottom row, everything but the first tile unseen.
+    fn corridor() -> (Map, MapObstacles, MapMemory) {
+        let mut map = Map(Grid::default([6, 3]));
+        for x in 0..6 {
+            map.0[[x, 0]] = MapTile::Floor;
+        }
+        let obstacles = MapObstacles::from_map(&map);
+        let mut memory = MapMemory(vec![false; map.0.len()]);
+        memory.0[0] = true;
+        (map, obstacles, memory)
+    }
+
+    #[test]
+    fn steps_towards_closest_unseen_tile() {
+        let (map, obstacles, memory) = corridor();
+        assert_eq!(Ok(IVec2::new(1, 0)), explore_step(&map, &obstacles, &memory, IVec2::ZERO));
+    }
+
+    #[test]
+    fn done_when_everything_reachable_is_seen() {
+        let (map, obstacles, mut memory) = corridor();
+        for seen in memory.0.iter_mut().take(6) {
+            *seen = true;
+        }
+        // The unseen walls can't be reached
+        assert_eq!(Err(ExploreError::Explored), explore_step(&map, &obstacles, &memory, IVec2::ZERO));
+    }
+
+    #[test]
+    fn actors_in_the_way() {
+        let (map, mut obstacles, memory) = corridor();
+        // A monster standing on the closest unseen tile
+        obstacles.0[[1, 0]] = true;
+        assert_eq!(Err(ExploreError::Blocked), explore_step(&map, &obstacles, &memory, IVec2::ZERO));
+    }
+
+    #[test]
+    fn goes_around_actors() {
+        let (mut map, mut obstacles, mut memory) = corridor();
+        // A seen corridor alongside the unseen one
+        for x in 0..6 {
+            map.0[[x, 1]] = MapTile::Floor;
+            obstacles.0[[x, 1]] = false;
+            memory.0[map.0.pos_to_index(IVec2::new(x, 1))] = true;
+        }
+        // A monster standing on the closest unseen tile
+        obstacles.0[[1, 0]] = true;
+
+        let step = explore_step(&map, &obstacles, &memory, IVec2::ZERO).unwrap();
+        assert!(!obstacles.0[step]);
+    }
+
+    #[test]
+    fn lava_is_avoided() {
+        let (mut map, obstacles, memory) = corridor();
+        map.0[[1, 0]] = MapTile::Lava;
+        assert_eq!(Err(ExploreError::Explored), explore_step(&map, &obstacles, &memory, IVec2::ZERO));
+    }
+}
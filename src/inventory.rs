@@ -0,0 +1,357 @@
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+    action::{Action, ActionEvent},
+    config,
+    equipment::{EquipSlot, Equippable},
+    monster::Monster,
+    movement::Position,
+    player::{InputMode, Player, PLAYER_INPUT_SYSTEM_LABEL},
+    render::Renderable,
+    replay::ReplayPlayback,
+    spawn_table::{self, SpawnEntry},
+    targeting::{visible_targets, Targeting},
+    turn_system::TakingATurn,
+    ui::INVENTORY_ROWS,
+    visibility::MapView,
+};
+
+/// Label for the system handling the inventory screen. Occurs in [CoreStage::PreUpdate].
+pub const INVENTORY_INPUT_SYSTEM_LABEL: &str = "inventory_input";
+
+pub const ITEMS_FILE_NAME: &str = "items.ron";
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ItemTemplates::load())
+        .init_resource::<InventoryCursor>()
+        .add_system_to_stage(CoreStage::PreUpdate, inventory_input
+            .label(INVENTORY_INPUT_SYSTEM_LABEL)
+            .before(PLAYER_INPUT_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// An entity that can be picked up. Items on the map have a [Position],
+/// items in an [Inventory] don't.
+#[derive(Component, Default, Debug)]
+pub struct Item;
+
+#[derive(Bundle)]
+pub struct ItemBundle {
+    pub item: Item,
+    pub name: Name,
+    pub renderable: Renderable,
+}
+
+/// Items carried by an actor.
+#[derive(Component, Debug)]
+pub struct Inventory {
+    pub capacity: usize,
+    pub items: Vec<Entity>,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.items.len() >= self.capacity
+    }
+}
+
+/// An item that is used up when the player uses it from their inventory.
+#[derive(Component, Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Consumable {
+    pub effect: ItemEffect,
+    /// Logged when the item is used.
+    pub message: String,
+}
+
+/// What a [Consumable] does when it's used.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ItemEffect {
+    /// Heal the user by the given amount.
+    Heal(i32),
+    /// Damage the first actor in the line of fire towards a target within range.
+    Damage { amount: i32, range: u32 },
+}
+
+impl ItemEffect {
+    /// How far away the effect can be aimed, for effects that need a target.
+    pub fn range(&self) -> Option<u32> {
+        match self {
+            ItemEffect::Heal(_) => None,
+            ItemEffect::Damage { range, .. } => Some(*range),
+        }
+    }
+}
+
+/// The selected slot on the inventory screen.
+#[derive(Default)]
+pub struct InventoryCursor(pub usize);
+
+/// A kind of item, read from [ITEMS_FILE_NAME].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemTemplate {
+    pub name: String,
+    pub glyph: char,
+    /// Foreground color as rgb, from 0.0 to 1.0.
+    pub color: (f32, f32, f32),
+    /// Items without one can't be used.
+    #[serde(default)]
+    pub consumable: Option<Consumable>,
+    /// Items without one can't be equipped.
+    #[serde(default)]
+    pub equippable: Option<Equippable>,
+}
+
+impl ItemTemplate {
+    pub fn bundle(&self) -> ItemBundle {
+        let (r, g, b) = self.color;
+        ItemBundle {
+            item: Item,
+            name: Name::new(self.name.clone()),
+            renderable: Renderable {
+                fg_color: Color::rgb(r, g, b),
+                bg_color: Color::BLACK,
+                glyph: self.glyph,
+            },
+        }
+    }
+
+    /// Spawn the item. It won't be on the map until it's given a [Position].
+    pub fn spawn<'w, 's, 'a>(&self, commands: &'a mut Commands<'w, 's>) -> EntityCommands<'w, 's, 'a> {
+        let mut item = commands.spawn_bundle(self.bundle());
+        if let Some(consumable) = &self.consumable {
+            item.insert(consumable.clone());
+        }
+        if let Some(equippable) = &self.equippable {
+            item.insert(equippable.clone());
+        }
+        item
+    }
+}
+
+/// Every kind of item and how likely they are to spawn, read from [ITEMS_FILE_NAME].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemTemplates {
+    pub items: Vec<ItemTemplate>,
+    pub spawn_table: Vec<SpawnEntry>,
+}
+
+impl ItemTemplates {
+    pub fn get(&self, name: &str) -> Option<&ItemTemplate> {
+        self.items.iter().find(|i| i.name == name)
+    }
+
+    /// Pick a random item from the spawn table for the given depth.
+    pub fn random_for_depth(&self, rng: &mut impl Rng, depth: u32) -> Option<&ItemTemplate> {
+        spawn_table::roll(&self.spawn_table, rng, depth).and_then(|name| self.get(name))
+    }
+
+    /// Spawn an item by name. It won't be on the map until it's given a [Position].
+    pub fn spawn(&self, commands: &mut Commands, name: &str) -> Option<Entity> {
+        match self.get(name) {
+            Some(template) => Some(template.spawn(commands).id()),
+            None => {
+                eprintln!("There is no item named '{}'", name);
+                None
+            }
+        }
+    }
+
+    /// Check for values that can't be used in game.
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, item) in self.items.iter().enumerate() {
+            if self.items[..i].iter().any(|other| other.name == item.name) {
+                return Err(format!("there is more than one item named '{}'", item.name));
+            }
+            match item.consumable.as_ref().map(|c| c.effect) {
+                Some(ItemEffect::Heal(amount)) if amount <= 0 => {
+                    return Err(format!("{} heals for {}, it must heal for more than 0", item.name, amount));
+                },
+                Some(ItemEffect::Damage { amount, .. }) if amount <= 0 => {
+                    return Err(format!("{} deals {} damage, it must deal more than 0", item.name, amount));
+                },
+                _ => {},
+            }
+            if let Some(equippable) = &item.equippable {
+                if equippable.attack.is_some() && equippable.slot != EquipSlot::MainHand {
+                    return Err(format!("{} has attack dice but isn't worn in the main hand", item.name));
+                }
+            }
+        }
+
+        spawn_table::validate(&self.spawn_table, |name| self.get(name).is_some())
+    }
+
+    pub fn try_load() -> Result<ItemTemplates, String> {
+        let templates: ItemTemplates = config::load_asset(ITEMS_FILE_NAME)?;
+
+        if let Err(e) = templates.validate() {
+            return Err(format!("Invalid items in {}: {}", ITEMS_FILE_NAME, e));
+        }
+
+        Ok(templates)
+    }
+
+    /// Read the items from the assets folder, falling back to the built in
+    /// healing potion if they can't be loaded.
+    pub fn load() -> ItemTemplates {
+        match ItemTemplates::try_load() {
+            Ok(templates) => templates,
+            Err(e) => {
+                eprintln!("{}. Using default items.", e);
+                ItemTemplates::default()
+            }
+        }
+    }
+}
+
+impl Default for ItemTemplates {
+    fn default() -> Self {
+        Self {
+            items: vec![
+                ItemTemplate {
+                    name: "Healing Potion".to_string(),
+                    glyph: '!',
+                    color: (1.0, 0.2, 0.6),
+                    consumable: Some(Consumable {
+                        effect: ItemEffect::Heal(8),
+                        message: "You drink the healing potion.".to_string(),
+                    }),
+                    equippable: None,
+                },
+            ],
+            spawn_table: vec![
+                SpawnEntry::new("Healing Potion", 10),
+            ],
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn inventory_input(
+    input: Res<Input<KeyCode>>,
+    mut mode: ResMut<InputMode>,
+    mut cursor: ResMut<InventoryCursor>,
+    mut targeting: ResMut<Targeting>,
+    q_player: Query<(Entity, &Inventory, &Position, &MapView, Option<&TakingATurn>), With<Player>>,
+    q_consumables: Query<&Consumable>,
+    q_monsters: Query<&Position, With<Monster>>,
+    replay: Option<Res<ReplayPlayback>>,
+    mut evt_action: EventWriter<ActionEvent>,
+) {
+    if replay.is_some() {
+        return;
+    }
+
+    match *mode {
+        InputMode::Game => {
+            if input.just_pressed(KeyCode::I) {
+                *mode = InputMode::Inventory;
+                cursor.0 = 0;
+            }
+        },
+        InputMode::Targeting | InputMode::Visualizer => {},
+        InputMode::Inventory => {
+            if input.just_pressed(KeyCode::I) || input.just_pressed(KeyCode::Escape) {
+                *mode = InputMode::Game;
+                return;
+            }
+
+            let (player, inventory, pos, view, taking_turn) = match q_player.get_single() {
+                Ok(player) => player,
+                Err(_) => return,
+            };
+
+            let count = inventory.items.len();
+            if count == 0 {
+                return;
+            }
+
+            let mut next = cursor.0.min(count - 1) as i32;
+            if input.just_pressed(KeyCode::Up) || input.just_pressed(KeyCode::Numpad8) {
+                next -= 1;
+            }
+            if input.just_pressed(KeyCode::Down) || input.just_pressed(KeyCode::Numpad2) {
+                next += 1;
+            }
+            if input.just_pressed(KeyCode::Left) || input.just_pressed(KeyCode::Numpad4) {
+                next -= INVENTORY_ROWS as i32;
+            }
+            if input.just_pressed(KeyCode::Right) || input.just_pressed(KeyCode::Numpad6) {
+                next += INVENTORY_ROWS as i32;
+            }
+            let next = next.clamp(0, count as i32 - 1) as usize;
+            if next != cursor.0 {
+                cursor.0 = next;
+            }
+
+            if taking_turn.is_some() {
+                let mut act = |action| evt_action.send(ActionEvent { actor: player, action });
+                if input.just_pressed(KeyCode::U) || input.just_pressed(KeyCode::Return) {
+                    let item = inventory.items[cursor.0];
+                    let range = q_consumables.get(item).ok().and_then(|c| c.effect.range());
+                    match range {
+                        Some(range) => {
+                            // Start aiming at the closest monster
+                            let targets = visible_targets(pos.0, view, range, q_monsters.iter());
+                            *targeting = Targeting {
+                                cursor: targets.first().copied().unwrap_or(pos.0),
+                                range,
+                                item: cursor.0,
+                            };
+                            *mode = InputMode::Targeting;
+                        },
+                        None => act(Action::UseItem(cursor.0)),
+                    }
+                } else if input.just_pressed(KeyCode::E) {
+                    act(Action::Equip(cursor.0));
+                } else if input.just_pressed(KeyCode::D) {
+                    act(Action::Drop(cursor.0));
+                }
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asset_items_are_valid() {
+        ItemTemplates::try_load().unwrap();
+    }
+
+    #[test]
+    fn consumable_must_have_an_effect() {
+        let mut templates = ItemTemplates::default();
+        templates.items[0].consumable = Some(Consumable {
+            effect: ItemEffect::Heal(0),
+            message: String::new(),
+        });
+        assert!(templates.validate().is_err());
+    }
+
+    #[test]
+    fn inventory_capacity() {
+        let mut inventory = Inventory::new(1);
+        assert!(!inventory.is_full());
+        inventory.items.push(Entity::from_raw(0));
+        assert!(inventory.is_full());
+    }
+}
@@ -0,0 +1,115 @@
+//! An ascii roguelike built with Bevy. [run] starts the game, the map
+//! generator can also be run on its own with the `mapgen` binary.
+//!
+//! Only what the `mapgen` binary needs is public: the settings, the map
+//! generator and the monster and item templates it spawns from.
+
+use bevy::{prelude::*, input::InputPlugin};
+
+use bevy_ascii_terminal::{TerminalBundle, TiledCameraBundle};
+
+pub use inventory::ItemTemplates;
+pub use monster::MonsterTemplates;
+
+pub mod config;
+pub mod map;
+pub mod map_gen;
+
+mod action;
+mod ai;
+mod bundle;
+mod camera;
+mod dijkstra;
+mod explore;
+mod map_state;
+mod monster;
+mod movement;
+mod player;
+mod render;
+mod shapes;
+mod visibility;
+mod ui;
+mod events;
+//mod web_resize;
+mod turn_system;
+mod combat;
+mod rng;
+mod replay;
+mod save;
+mod dungeon;
+mod spawn_table;
+mod inventory;
+mod equipment;
+mod targeting;
+mod visualizer;
+#[cfg(test)]
+mod test_util;
+
+#[derive(Component)]
+pub(crate) struct GameTerminal;
+
+pub(crate) const VIEWPORT_SIZE: [u32;2] = [80,40];
+
+pub(crate) const UI_SIZE: [u32;2] = [VIEWPORT_SIZE[0],8];
+/// The size of the terminal the map is drawn to. Maps can be larger, the
+/// [camera::MapViewport] scrolls to follow the player.
+pub(crate) const GAME_SIZE: [u32;2] = [VIEWPORT_SIZE[0], VIEWPORT_SIZE[1] - UI_SIZE[1]];
+
+fn setup(mut commands: Commands) {
+    //commands.spawn().insert(gen.map);
+
+    let term_y = VIEWPORT_SIZE[1] as f32 / 2.0 - GAME_SIZE[1] as f32 / 2.0; 
+    let term_bundle = TerminalBundle {
+        transform: Transform::from_xyz(0.0, term_y, 0.0),
+        ..TerminalBundle::new().with_size(GAME_SIZE)
+    };
+    //term_bundle.transform = Transform::from_xyz(0.0, 0.0, UI_SIZE[1] as f32 * 2.0);
+    commands.spawn_bundle(term_bundle).insert(GameTerminal);
+
+    let totalx = GAME_SIZE[0];
+    let totaly = GAME_SIZE[1] + UI_SIZE[1];
+    commands.spawn_bundle(TiledCameraBundle::new().with_tile_count([totalx, totaly]));
+}
+
+/// Start the game, see `README.md` for the command line options.
+pub fn run() {
+    let mut app = App::new();
+
+    // Headless runs are only used to play back replays
+    if config::has_arg("--headless") {
+        app.add_plugins(MinimalPlugins)
+        .add_plugin(InputPlugin)
+        .init_resource::<ui::PrintLog>();
+    } else {
+        app.add_plugins(DefaultPlugins)
+        .add_plugin(camera::CameraPlugin)
+        .add_plugin(render::RenderPlugin)
+        .add_plugin(ui::UiPlugin)
+        .add_plugin(visualizer::VisualizerPlugin)
+        .add_startup_system(setup)
+        .insert_resource(ClearColor(Color::BLACK));
+    }
+
+    app
+        // Must be added before the map and rng plugins so a replay or save can provide the seed and settings
+        .add_plugin(replay::ReplayPlugin)
+        .add_plugin(save::SavePlugin)
+        .add_plugin(rng::RngPlugin)
+        .add_plugin(player::PlayerPlugin)
+        .add_plugin(action::ActionPlugin)
+        .add_plugin(explore::ExplorePlugin)
+        .add_plugin(map::MapGenPlugin)
+        .add_plugin(dungeon::DungeonPlugin)
+        .add_plugin(events::EventsPlugin)
+        .add_plugin(visibility::VisiblityPlugin)
+        .add_plugin(map_state::MapStatePlugin)
+        //.add_plugin(web_resize::FullViewportPlugin)
+        .add_plugin(turn_system::TurnSystemPlugin)
+        .add_plugin(monster::MonstersPlugin)
+        .add_plugin(dijkstra::DijkstraPlugin)
+        .add_plugin(inventory::InventoryPlugin)
+        .add_plugin(equipment::EquipmentPlugin)
+        .add_plugin(targeting::TargetingPlugin)
+        .add_plugin(combat::CombatPlugin)
+        .run();
+}
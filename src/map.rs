@@ -1,13 +1,13 @@
 use bevy::{
     math::{IVec2},
     prelude::*,
-    utils::HashSet,
 };
 use bevy_ascii_terminal::Side;
-use rand::{prelude::{StdRng, ThreadRng}, Rng, SeedableRng};
+use rand::{prelude::StdRng, Rng, SeedableRng};
 use sark_grids::Grid;
+use serde::{Deserialize, Serialize};
 
-use crate::{config::{MapGenSettings}, monster::MonsterBundle, player::{Player}, shapes::Rect, GAME_SIZE, movement::Position};
+use crate::{config::{self, MapGenSettings}, map_gen::{self, MapHistory, Vaults}, monster::MonsterTemplates, inventory::ItemTemplates, player::{Player}, shapes::Rect, movement::Position, rng::RunSeed, save::LoadedGame, turn_system::MOVE_COST, visualizer::{MapGenPlayback, MapGenVisualizer}};
 
 pub struct MapGenPlugin;
 
@@ -15,44 +15,186 @@ pub const MAP_GEN_SETUP_LABEL: &str = "MAP_GEN_SETUP";
 
 impl Plugin for MapGenPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup
+        // Settings may have already been provided, ie: by a replay
+        if !app.world.contains_resource::<MapGenSettings>() {
+            app.insert_resource(load_settings());
+        }
+        app.insert_resource(Vaults::load())
+        .add_startup_system(setup
             //.after(PLAYER_SETUP_LABEL)
             .label(MAP_GEN_SETUP_LABEL)
         );
     }
 }
 
+fn load_settings() -> MapGenSettings {
+    let mut settings = config::get_map_settings();
+    if let Err(e) = settings.validate() {
+        eprintln!("{}. Using default map settings.", e);
+        settings = MapGenSettings::default();
+    }
+    settings
+}
+
+#[allow(clippy::too_many_arguments)]
 fn setup(
     mut commands: Commands,
+    settings: Res<MapGenSettings>,
+    seed: Res<RunSeed>,
+    loaded: Option<Res<LoadedGame>>,
+    monsters: Res<MonsterTemplates>,
+    items: Res<ItemTemplates>,
+    vaults: Res<Vaults>,
+    visualizer: Option<Res<MapGenVisualizer>>,
     q_player: Query<(Entity,&Player)>,
 ) {
-  // Gen map
-    // let mut settings = match config::try_get_map_settings() {
-    //     Ok(settings) => settings,
-    //     Err(e) => panic!("{}", e),
-    // };
-
-    let mut settings = MapGenSettings::default();
-    settings.map_size = GAME_SIZE;
+    // The map will be restored from the save instead
+    if loaded.is_some() {
+        return;
+    }
 
-    //settings.map_size;
+    if let Err(e) = vaults.validate_spawns(&monsters, &items) {
+        eprintln!("Invalid vaults in {}: {}", map_gen::VAULTS_FILE_NAME, e);
+    }
 
-    //let rng = StdRng::seed_from_u64(settings.seed);
-    let rng = StdRng::from_rng(ThreadRng::default()).unwrap();
+    let rng = level_rng(seed.0, 1);
 
     let player = q_player.get_single().map_or_else(|_|None,|(e,_)|Some(e));
     let entities = MapGenEntities {
         player,
     };
 
-    MapGenerator::build(&mut commands, settings, rng, entities);
+    let assets = MapGenAssets {
+        monsters: &monsters,
+        items: &items,
+        vaults: &vaults,
+    };
+    MapGenerator::build(&mut commands, &settings, rng, entities, &assets, 1, visualizer.is_some());
+}
+
+/// The random number generator for a level of the dungeon, derived from the run seed.
+pub fn level_rng(seed: u64, depth: u32) -> StdRng {
+    let offset = (depth.saturating_sub(1) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    StdRng::seed_from_u64(seed ^ offset)
 }
 
-/// A tile on the [Map].
-#[derive(Eq, PartialEq, Clone, Copy)]
+/// A tile on the [Map]. How each kind of tile looks and behaves is defined
+/// in [MapTile::properties].
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum MapTile {
     Wall,
     Floor,
+    /// Leads to the next level of the dungeon.
+    DownStairs,
+    /// Leads back to the previous level of the dungeon.
+    UpStairs,
+    ClosedDoor,
+    OpenDoor,
+    /// Slows down anything wading through it.
+    ShallowWater,
+    /// Too deep to cross, but can be seen and shot over.
+    DeepWater,
+    /// Burns anything that steps in it.
+    Lava,
+    /// Slows down anything climbing over it.
+    Rubble,
+    Grass,
+}
+
+/// How a kind of [MapTile] looks and behaves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileProperties {
+    pub glyph: char,
+    pub fg_color: Color,
+    pub bg_color: Color,
+    /// Whether actors can stand on the tile.
+    pub walkable: bool,
+    /// Whether actors can see and shoot through the tile.
+    pub transparent: bool,
+    /// The energy it costs to move onto the tile. A normal move costs [MOVE_COST].
+    pub move_cost: i32,
+    /// What happens to an actor when it moves onto the tile.
+    pub on_enter: Option<TileEffect>,
+    /// The tile this turns into when an actor bumps into it, ie: a closed door opening.
+    pub opens_to: Option<MapTile>,
+}
+
+/// Something that happens to an actor when it moves onto a tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileEffect {
+    /// Damage the actor, ignoring defense.
+    Burn(i32),
+}
+
+pub const WALL_COLOR: Color = Color::Rgba{ red:0.866, green:0.866, blue:0.882, alpha: 1.0};
+pub const FLOOR_COLOR: Color = Color::Rgba{ red:0.602, green:0.462, blue:0.325, alpha: 1.0};
+pub const STAIRS_COLOR: Color = Color::Rgba{ red:0.945, green:0.804, blue:0.251, alpha: 1.0};
+pub const DOOR_COLOR: Color = Color::Rgba{ red:0.698, green:0.420, blue:0.141, alpha: 1.0};
+pub const SHALLOW_WATER_COLOR: Color = Color::Rgba{ red:0.376, green:0.663, blue:0.898, alpha: 1.0};
+pub const DEEP_WATER_COLOR: Color = Color::Rgba{ red:0.125, green:0.290, blue:0.678, alpha: 1.0};
+pub const LAVA_COLOR: Color = Color::Rgba{ red:0.969, green:0.408, blue:0.098, alpha: 1.0};
+pub const RUBBLE_COLOR: Color = Color::Rgba{ red:0.545, green:0.525, blue:0.502, alpha: 1.0};
+pub const GRASS_COLOR: Color = Color::Rgba{ red:0.361, green:0.667, blue:0.247, alpha: 1.0};
+
+/// Damage taken from stepping into lava.
+const LAVA_DAMAGE: i32 = 6;
+
+impl MapTile {
+    /// The definition of every kind of tile.
+    pub const fn properties(self) -> TileProperties {
+        const fn tile(glyph: char, fg_color: Color, walkable: bool, transparent: bool) -> TileProperties {
+            TileProperties {
+                glyph,
+                fg_color,
+                bg_color: Color::BLACK,
+                walkable,
+                transparent,
+                move_cost: MOVE_COST,
+                on_enter: None,
+                opens_to: None,
+            }
+        }
+
+        match self {
+            MapTile::Wall => tile('#', WALL_COLOR, false, false),
+            MapTile::Floor => tile('.', FLOOR_COLOR, true, true),
+            MapTile::DownStairs => tile('>', STAIRS_COLOR, true, true),
+            MapTile::UpStairs => tile('<', STAIRS_COLOR, true, true),
+            MapTile::ClosedDoor => TileProperties {
+                opens_to: Some(MapTile::OpenDoor),
+                ..tile('+', DOOR_COLOR, false, false)
+            },
+            MapTile::OpenDoor => tile('\'', DOOR_COLOR, true, true),
+            MapTile::ShallowWater => TileProperties {
+                move_cost: MOVE_COST * 2,
+                ..tile('~', SHALLOW_WATER_COLOR, true, true)
+            },
+            MapTile::DeepWater => tile('~', DEEP_WATER_COLOR, false, true),
+            MapTile::Lava => TileProperties {
+                on_enter: Some(TileEffect::Burn(LAVA_DAMAGE)),
+                ..tile('=', LAVA_COLOR, true, true)
+            },
+            MapTile::Rubble => TileProperties {
+                move_cost: MOVE_COST * 3 / 2,
+                ..tile(':', RUBBLE_COLOR, true, true)
+            },
+            MapTile::Grass => tile('"', GRASS_COLOR, true, true),
+        }
+    }
+
+    pub const fn is_walkable(self) -> bool {
+        self.properties().walkable
+    }
+
+    pub const fn is_opaque(self) -> bool {
+        !self.properties().transparent
+    }
+
+    /// Whether actors can get through the tile, opening it first if need be.
+    pub const fn is_passable(self) -> bool {
+        let properties = self.properties();
+        properties.walkable || properties.opens_to.is_some()
+    }
 }
 
 impl Default for MapTile {
@@ -69,92 +211,87 @@ pub struct MapGenEntities {
     //pub monsters: Vec<MonsterBundle>,
 }
 
-pub struct MapGenerator {
-    pub map: Map,
-    pub rooms: Vec<Rect>,
+/// The templates used to fill a level with monsters, items and vaults.
+pub struct MapGenAssets<'a> {
+    pub monsters: &'a MonsterTemplates,
+    pub items: &'a ItemTemplates,
+    pub vaults: &'a Vaults,
 }
 
+/// Builds levels with [map_gen::build_level] and spawns them into the world.
+pub struct MapGenerator;
+
 impl MapGenerator {
     pub fn build(
         commands: &mut Commands,
-        settings: MapGenSettings,
+        settings: &MapGenSettings,
         mut rng: StdRng,
         entities: MapGenEntities,
+        assets: &MapGenAssets,
+        depth: u32,
+        record: bool,
     ) {
-        let mut map = Map(Grid::default(settings.map_size));
-        let mut rooms: Vec<Rect> = Vec::with_capacity(50);
-
-        generate_rooms(&mut map, &settings, &mut rng, &mut rooms);
-
-        let map = MapGenerator { map, rooms };
+        let mut state = map_gen::build_level(settings, assets, &mut rng, depth, record);
 
         if let Some(player) = entities.player {
-            map.place_player(commands, player);
+            let p = state.start();
+            // Set the player's position
+            commands.entity(player).insert(Position::from(p));
+            info!("Setting player position to {}", p);
         } else {
-            println!("No player found");
+            warn!("No player found");
         }
 
-        let mut placed: HashSet<IVec2> = HashSet::default();
-
-        map.place_monsters(commands, &settings, &mut rng, &mut placed);
-
-        commands.spawn().insert(map.map);
-    }
+        for spawn in state.monsters.iter() {
+            match assets.monsters.get(&spawn.name) {
+                Some(template) => {
+                    let mut monster = template.bundle();
+                    monster.scale_to_depth(depth);
+                    monster.movable.position = spawn.position.into();
+                    commands.spawn_bundle(monster);
+                },
+                None => eprintln!("There is no monster named '{}'", spawn.name),
+            }
+        }
+        for spawn in state.items.iter() {
+            if let Some(item) = assets.items.spawn(commands, &spawn.name) {
+                commands.entity(item).insert(Position::from(spawn.position));
+            }
+        }
 
-    pub fn place_player(&self, commands: &mut Commands, player: Entity) {
-        let p = self.rooms[0].center();
+        if record {
+            let snapshots = std::mem::take(&mut state.history.snapshots);
+            commands.insert_resource(MapGenPlayback::new(snapshots));
+        }
 
-        // Set the player's position
-        commands.entity(player).insert(Position::from(p));
-        println!("Setting player position to {}", p);
+        commands.spawn().insert(state.map);
     }
+}
 
-    pub fn place_monsters(
-        &self,
-        commands: &mut Commands,
-        settings: &MapGenSettings,
-        rng: &mut StdRng,
-        placed: &mut HashSet<IVec2>,
-    ) {
-        // The first room is the player's room
-        for room in self.rooms.iter().skip(1) {
-            let count = rng.gen_range(settings.monsters_per_room.clone());
-
-            for _ in 0..=count {
-                for _ in 0..2 {
-                    // If the first try fails, try again
-                    let p = get_random_ivec(rng, room.min, room.max);
-
-                    if placed.contains(&p) {
-                        continue;
-                    }
-
-                    let monster_index = rng.gen_range(0..MonsterBundle::max_index());
-                    let mut monster = MonsterBundle::get_from_index(monster_index);
-                    monster.movable.position = p.into();
-                    placed.insert(p);
-
-                    commands.spawn_bundle(monster);
-
-                    break;
-                }
-            }
-        }
+/// Fall back to the whole map as a spawn region, carving out a spot in the
+/// center if there's no floor at all.
+pub fn ensure_spawn_region(map: &mut Map, rooms: &mut Vec<Rect>) {
+    let size = map.0.size().as_ivec2();
+    if !map.0.iter().any(|t| *t == MapTile::Floor) {
+        let center = Rect::from_position_size((size / 2 - IVec2::ONE).into(), (3, 3));
+        build_room(map, &center);
     }
+    rooms.push(Rect::from_position_size((1, 1), (size.x - 2, size.y - 2)));
 }
 
-fn get_random_ivec(rng: &mut StdRng, min: IVec2, max: IVec2) -> IVec2 {
+pub fn get_random_ivec(rng: &mut StdRng, min: IVec2, max: IVec2) -> IVec2 {
     let p_x = rng.gen_range(min.x..max.x);
     let p_y = rng.gen_range(min.y..max.y);
 
     IVec2::new(p_x, p_y)
 }
 
-fn generate_rooms(
+pub fn generate_rooms(
     map: &mut Map,
     settings: &MapGenSettings,
     rng: &mut StdRng,
     rooms: &mut Vec<Rect>,
+    history: &mut MapHistory,
 ) {
     for _ in 0..settings.iterations {
         let w = rng.gen_range(settings.room_size.clone());
@@ -180,10 +317,12 @@ fn generate_rooms(
         if ok {
             //println!("Building new room!");
             build_room(map, &new_room);
+            history.record(map, "Room");
 
             if !rooms.is_empty() {
                 let prev_room = &rooms[rooms.len() - 1];
                 build_tunnels_between_rooms(map, rng, prev_room, &new_room);
+                history.record(map, "Tunnel");
             }
 
             rooms.push(new_room);
@@ -191,13 +330,13 @@ fn generate_rooms(
     }
 }
 
-fn build_room(map: &mut Map, room: &Rect) {
+pub fn build_room(map: &mut Map, room: &Rect) {
     for pos in room.iter() {
         map.0[pos] = MapTile::Floor;
     }
 }
 
-fn build_tunnels_between_rooms(map: &mut Map, rng: &mut StdRng, room_a: &Rect, room_b: &Rect) {
+pub fn build_tunnels_between_rooms(map: &mut Map, rng: &mut StdRng, room_a: &Rect, room_b: &Rect) {
     let (new_x, new_y) = room_b.center().into();
     let (prev_x, prev_y) = room_a.center().into();
 
@@ -210,7 +349,7 @@ fn build_tunnels_between_rooms(map: &mut Map, rng: &mut StdRng, room_a: &Rect, r
     }
 }
 
-fn build_horizontal_tunnel(map: &mut Map, x1: i32, x2: i32, y: i32) {
+pub fn build_horizontal_tunnel(map: &mut Map, x1: i32, x2: i32, y: i32) {
     let min = x1.min(x2);
     let max = x1.max(x2);
 
@@ -219,7 +358,7 @@ fn build_horizontal_tunnel(map: &mut Map, x1: i32, x2: i32, y: i32) {
     }
 }
 
-fn build_vertical_tunnel(map: &mut Map, y1: i32, y2: i32, x: i32) {
+pub fn build_vertical_tunnel(map: &mut Map, y1: i32, y2: i32, x: i32) {
     let min = y1.min(y2);
     let max = y1.max(y2);
 
@@ -227,3 +366,65 @@ fn build_vertical_tunnel(map: &mut Map, y1: i32, y2: i32, x: i32) {
         map.0[ [x as u32, y as u32] ] = MapTile::Floor;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use rand::{prelude::StdRng, SeedableRng};
+    use sark_grids::Grid;
+
+    use crate::{config::MapGenSettings, map_gen::MapHistory, turn_system::MOVE_COST};
+
+    use super::{ensure_spawn_region, generate_rooms, Map, MapTile, TileEffect};
+
+    fn generate(seed: u64) -> (Map, Vec<(i32, i32, i32, i32)>) {
+        let settings = MapGenSettings::default();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut map = Map(Grid::default(settings.map_size));
+        let mut rooms = Vec::new();
+        generate_rooms(&mut map, &settings, &mut rng, &mut rooms, &mut MapHistory::default());
+        let rooms = rooms
+            .iter()
+            .map(|r| (r.min.x, r.min.y, r.max.x, r.max.y))
+            .collect();
+        (map, rooms)
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let (map_a, rooms_a) = generate(1234);
+        let (map_b, rooms_b) = generate(1234);
+
+        assert_eq!(rooms_a, rooms_b);
+        assert!(map_a.0.iter().eq(map_b.0.iter()));
+    }
+
+    #[test]
+    fn map_without_rooms() {
+        let mut map = Map(Grid::default([20, 20]));
+        let mut rooms = Vec::new();
+        ensure_spawn_region(&mut map, &mut rooms);
+
+        assert_eq!(1, rooms.len());
+        assert!(map.0.iter().any(|t| *t == MapTile::Floor));
+    }
+
+    #[test]
+    fn tile_properties() {
+        assert!(!MapTile::Wall.is_walkable());
+        assert!(MapTile::Wall.is_opaque());
+        assert!(MapTile::ClosedDoor.is_opaque());
+        assert!(!MapTile::OpenDoor.is_opaque());
+
+        // Deep water blocks movement but not sight
+        assert!(!MapTile::DeepWater.is_walkable());
+        assert!(!MapTile::DeepWater.is_opaque());
+
+        assert_eq!(MOVE_COST, MapTile::Floor.properties().move_cost);
+        assert!(MapTile::ShallowWater.properties().move_cost > MOVE_COST);
+        assert!(matches!(MapTile::Lava.properties().on_enter, Some(TileEffect::Burn(_))));
+
+        // Closed doors can be opened, so paths go through them
+        assert!(MapTile::ClosedDoor.is_passable());
+        assert_eq!(Some(MapTile::OpenDoor), MapTile::ClosedDoor.properties().opens_to);
+    }
+}
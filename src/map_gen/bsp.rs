@@ -0,0 +1,157 @@
+use std::ops::Range;
+
+use bevy::math::IVec2;
+use rand::{prelude::StdRng, Rng};
+
+use crate::{
+    config::MapGenSettings,
+    map::{build_room, build_tunnels_between_rooms, Map},
+    shapes::Rect,
+};
+
+use super::MapHistory;
+
+/// Fill the map with rooms using binary space partitioning.
+///
+/// The map is split in half recursively until each area can hold at most one
+/// room. Each area gets a room and the two halves of every split are joined
+/// by a tunnel, so every room is reachable.
+pub fn generate_bsp_rooms(
+    map: &mut Map,
+    settings: &MapGenSettings,
+    rng: &mut StdRng,
+    rooms: &mut Vec<Rect>,
+    history: &mut MapHistory,
+) {
+    let size = map.0.size().as_ivec2();
+    let bounds = Rect::from_extents((1, 1), (size.x - 1, size.y - 1));
+    split(map, settings, rng, bounds, rooms, history);
+}
+
+/// Build rooms in the area, returning the indices of the new rooms.
+fn split(
+    map: &mut Map,
+    settings: &MapGenSettings,
+    rng: &mut StdRng,
+    area: Rect,
+    rooms: &mut Vec<Rect>,
+    history: &mut MapHistory,
+) -> Range<usize> {
+    let start = rooms.len();
+
+    // Leave a wall on each side of the room
+    let min_leaf = settings.room_size.start as i32 + 2;
+    let max_leaf = settings.room_size.end as i32 + 1;
+
+    let can_split_x = area.width() >= min_leaf * 2;
+    let can_split_y = area.height() >= min_leaf * 2;
+    let too_big = area.width() > max_leaf || area.height() > max_leaf;
+
+    if !too_big || !(can_split_x || can_split_y) {
+        let room = leaf_room(settings, rng, &area);
+        build_room(map, &room);
+        history.record(map, "Room");
+        rooms.push(room);
+        return start..rooms.len();
+    }
+
+    // Split along the longest axis that can be split
+    let along_x = match (can_split_x, can_split_y) {
+        (true, true) => area.width() >= area.height(),
+        (x, _) => x,
+    };
+
+    let (a, b) = if along_x {
+        let at = rng.gen_range(min_leaf..=area.width() - min_leaf);
+        (
+            Rect::from_position_size(area.min.into(), (at, area.height())),
+            Rect::from_position_size((area.min.x + at, area.min.y), (area.width() - at, area.height())),
+        )
+    } else {
+        let at = rng.gen_range(min_leaf..=area.height() - min_leaf);
+        (
+            Rect::from_position_size(area.min.into(), (area.width(), at)),
+            Rect::from_position_size((area.min.x, area.min.y + at), (area.width(), area.height() - at)),
+        )
+    };
+
+    let left = split(map, settings, rng, a, rooms, history);
+    let right = split(map, settings, rng, b, rooms, history);
+
+    // Join the siblings through their closest rooms
+    let (i, j) = closest_rooms(rooms, left, right);
+    build_tunnels_between_rooms(map, rng, &rooms[i], &rooms[j]);
+    history.record(map, "Tunnel");
+
+    start..rooms.len()
+}
+
+/// A random room that fits inside the area, leaving a wall on every side.
+fn leaf_room(settings: &MapGenSettings, rng: &mut StdRng, area: &Rect) -> Rect {
+    let w = (rng.gen_range(settings.room_size.clone()) as i32).min(area.width() - 2);
+    let h = (rng.gen_range(settings.room_size.clone()) as i32).min(area.height() - 2);
+
+    let x = area.min.x + 1 + rng.gen_range(0..=area.width() - 2 - w);
+    let y = area.min.y + 1 + rng.gen_range(0..=area.height() - 2 - h);
+
+    Rect::from_position_size((x, y), (w, h))
+}
+
+fn closest_rooms(rooms: &[Rect], a: Range<usize>, b: Range<usize>) -> (usize, usize) {
+    let distance = |i: usize, j: usize| {
+        let d: IVec2 = rooms[i].center() - rooms[j].center();
+        d.x.abs() + d.y.abs()
+    };
+
+    let mut closest = (a.start, b.start);
+    for i in a {
+        for j in b.clone() {
+            if distance(i, j) < distance(closest.0, closest.1) {
+                closest = (i, j);
+            }
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::IVec2;
+    use rand::{prelude::StdRng, SeedableRng};
+    use sark_grids::Grid;
+
+    use crate::{config::MapGenSettings, map::{Map, MapTile}};
+
+    use super::{generate_bsp_rooms, MapHistory};
+
+    #[test]
+    fn rooms_are_connected() {
+        let settings = MapGenSettings::default();
+
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = Map(Grid::default(settings.map_size));
+            let mut rooms = Vec::new();
+            generate_bsp_rooms(&mut map, &settings, &mut rng, &mut rooms, &mut MapHistory::default());
+
+            assert!(rooms.len() > 1);
+
+            // Flood fill from the first room
+            let mut reached = Grid::<bool>::default(settings.map_size);
+            let mut open = vec![rooms[0].center()];
+            while let Some(p) = open.pop() {
+                if reached[p] || map.0[p] == MapTile::Wall {
+                    continue;
+                }
+                reached[p] = true;
+                for dir in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+                    open.push(p + dir);
+                }
+            }
+
+            for room in rooms.iter() {
+                assert!(reached[room.center()], "seed {}: {} isn't connected", seed, room);
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use bevy::math::IVec2;
+use rand::{prelude::StdRng, Rng};
+
+use crate::{
+    config::MapGenSettings,
+    map::{build_horizontal_tunnel, build_vertical_tunnel, Map, MapTile},
+    shapes::Rect,
+};
+
+use super::{floor_regions, spawn_regions, MapHistory, MIN_REGION_SIZE};
+
+/// Chance for each tile to start out as a wall.
+const FILL_CHANCE: f64 = 0.45;
+const SMOOTHING_PASSES: u32 = 4;
+
+/// Generate a cave using cellular automata.
+///
+/// The map starts as random noise which is smoothed into caverns. Isolated
+/// pockets are then tunneled into the main cavern or filled in, so every
+/// floor tile can be reached. The map is divided into spawn regions, see
+/// [spawn_regions].
+pub fn generate_caves(
+    map: &mut Map,
+    settings: &MapGenSettings,
+    rng: &mut StdRng,
+    rooms: &mut Vec<Rect>,
+    history: &mut MapHistory,
+) {
+    let size = map.0.size().as_ivec2();
+
+    // The border is left as walls
+    for y in 1..size.y - 1 {
+        for x in 1..size.x - 1 {
+            map.0[[x as u32, y as u32]] = match rng.gen_bool(FILL_CHANCE) {
+                true => MapTile::Wall,
+                false => MapTile::Floor,
+            };
+        }
+    }
+    history.record(map, "Noise");
+
+    for _ in 0..SMOOTHING_PASSES {
+        smooth(map);
+        history.record(map, "Smooth");
+    }
+
+    connect_pockets(map);
+    history.record(map, "Connect pockets");
+    spawn_regions(map, settings, rooms);
+}
+
+/// A tile becomes a wall if most of its neighbours are walls, otherwise it becomes floor.
+fn smooth(map: &mut Map) {
+    let size = map.0.size().as_ivec2();
+    let before: Vec<MapTile> = map.0.iter().copied().collect();
+    let is_wall = |p: IVec2| before[map.0.pos_to_index(p)] == MapTile::Wall;
+
+    let mut after = before.clone();
+    for y in 1..size.y - 1 {
+        for x in 1..size.x - 1 {
+            let p = IVec2::new(x, y);
+            let mut walls = 0;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    if is_wall(p + IVec2::new(dx, dy)) {
+                        walls += 1;
+                    }
+                }
+            }
+            after[map.0.pos_to_index(p)] = if walls >= 5 { MapTile::Wall } else { MapTile::Floor };
+        }
+    }
+
+    for (i, tile) in after.into_iter().enumerate() {
+        map.0[i] = tile;
+    }
+}
+
+/// Tunnel every pocket of floor into the largest cavern, or fill it in if it's too small.
+fn connect_pockets(map: &mut Map) {
+    let regions = floor_regions(map);
+    let main = match regions.first() {
+        Some(main) => main,
+        None => return,
+    };
+
+    for pocket in regions.iter().skip(1) {
+        if pocket.len() < MIN_REGION_SIZE {
+            for p in pocket.iter() {
+                map.0[*p] = MapTile::Wall;
+            }
+            continue;
+        }
+
+        let from = pocket[0];
+        let to = *main
+            .iter()
+            .min_by_key(|p| (**p - from).abs().x + (**p - from).abs().y)
+            .unwrap();
+        build_horizontal_tunnel(map, from.x, to.x, from.y);
+        build_vertical_tunnel(map, from.y, to.y, to.x);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{prelude::StdRng, SeedableRng};
+    use sark_grids::Grid;
+
+    use crate::{config::MapGenSettings, map::Map, map_gen::{floor_regions, MapHistory}};
+
+    use super::generate_caves;
+
+    #[test]
+    fn caves_are_connected() {
+        let settings = MapGenSettings::default();
+
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = Map(Grid::default(settings.map_size));
+            let mut rooms = Vec::new();
+            generate_caves(&mut map, &settings, &mut rng, &mut rooms, &mut MapHistory::default());
+
+            assert_eq!(1, floor_regions(&map).len(), "seed {}", seed);
+            assert!(!rooms.is_empty());
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use sark_grids::Grid;
+
+use crate::map::{Map, MapTile};
+
+use super::pipeline::Spawn;
+
+/// A copy of the map taken part way through building it.
+#[derive(Debug, Clone)]
+pub struct MapSnapshot {
+    /// What was just done, ie: `"Room"`, `"Tunnel"` or a build step.
+    pub label: String,
+    pub tiles: Grid<MapTile>,
+    pub monsters: Vec<Spawn>,
+    pub items: Vec<Spawn>,
+}
+
+/// Snapshots of the map taken as it's built, played back by the
+/// [crate::visualizer].
+#[derive(Debug, Default)]
+pub struct MapHistory {
+    /// Nothing is recorded unless this is set, so normal builds don't pay
+    /// for the copies.
+    pub enabled: bool,
+    pub snapshots: Vec<MapSnapshot>,
+}
+
+impl MapHistory {
+    /// Record the map's tiles.
+    pub fn record(&mut self, map: &Map, label: &str) {
+        self.record_with_spawns(map, label, &[], &[]);
+    }
+
+    /// Record the map's tiles along with what will be spawned on it.
+    pub fn record_with_spawns(&mut self, map: &Map, label: &str, monsters: &[Spawn], items: &[Spawn]) {
+        if !self.enabled {
+            return;
+        }
+
+        self.snapshots.push(MapSnapshot {
+            label: label.to_string(),
+            tiles: map.0.clone(),
+            monsters: monsters.to_vec(),
+            items: items.to_vec(),
+        });
+    }
+}
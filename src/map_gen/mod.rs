@@ -0,0 +1,103 @@
+//! Map generation algorithms, selected with [crate::config::MapGenAlgorithm],
+//! and the build steps run after them, see [crate::config::BuildStep].
+
+use bevy::math::IVec2;
+use sark_grids::Grid;
+
+use crate::{
+    config::MapGenSettings,
+    map::{Map, MapTile},
+    shapes::Rect,
+};
+
+mod bsp;
+mod cave;
+mod history;
+mod pipeline;
+mod stats;
+mod vault;
+mod walk;
+
+pub use bsp::generate_bsp_rooms;
+pub use cave::generate_caves;
+pub use history::{MapHistory, MapSnapshot};
+pub use pipeline::{build_level, BuildState, Spawn};
+pub use stats::MapStats;
+pub use vault::{stamp_vault, VaultGlyph, Vaults, VAULTS_FILE_NAME};
+pub use walk::{generate_walk, WalkKind};
+
+/// Areas of floor smaller than this are too small to bother with.
+pub const MIN_REGION_SIZE: usize = 8;
+
+/// Every separate area of passable tiles on the map, largest first.
+pub fn floor_regions(map: &Map) -> Vec<Vec<IVec2>> {
+    let mut seen = Grid::<bool>::default(map.0.size());
+    let mut regions = Vec::new();
+
+    for i in 0..map.0.len() {
+        if seen[i] || !map.0[i].is_passable() {
+            continue;
+        }
+
+        let mut region = Vec::new();
+        let mut open = vec![map.0.index_to_pos(i)];
+        seen[i] = true;
+        while let Some(p) = open.pop() {
+            region.push(p);
+            for dir in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+                let next = p + dir;
+                if map.0.in_bounds(next) && !seen[next] && map.0[next].is_passable() {
+                    seen[next] = true;
+                    open.push(next);
+                }
+            }
+        }
+        regions.push(region);
+    }
+
+    regions.sort_by_key(|r| std::cmp::Reverse(r.len()));
+    regions
+}
+
+/// Every tile that can be walked to from `start`.
+pub fn reachable_from(map: &Map, start: IVec2) -> Grid<bool> {
+    let mut reached = Grid::<bool>::default(map.0.size());
+    if !map.0[start].is_passable() {
+        return reached;
+    }
+
+    let mut open = vec![start];
+    reached[start] = true;
+    while let Some(p) = open.pop() {
+        for dir in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+            let next = p + dir;
+            if map.0.in_bounds(next) && !reached[next] && map.0[next].is_passable() {
+                reached[next] = true;
+                open.push(next);
+            }
+        }
+    }
+    reached
+}
+
+/// Divide the map into spawn regions for generators that don't make rooms.
+///
+/// Regions are squares of `room_size`, those with too little floor are
+/// skipped. Unlike rooms, spawn regions may contain walls.
+pub fn spawn_regions(map: &Map, settings: &MapGenSettings, rooms: &mut Vec<Rect>) {
+    let size = map.0.size().as_ivec2();
+    let step = settings.room_size.end as i32;
+
+    for y in (1..size.y - 1).step_by(step as usize) {
+        for x in (1..size.x - 1).step_by(step as usize) {
+            let w = step.min(size.x - 1 - x);
+            let h = step.min(size.y - 1 - y);
+            let area = Rect::from_position_size((x, y), (w, h));
+
+            let floor = area.iter().filter(|p| map.0[*p] == MapTile::Floor).count();
+            if floor >= MIN_REGION_SIZE {
+                rooms.push(area);
+            }
+        }
+    }
+}
@@ -0,0 +1,627 @@
+use bevy::{math::IVec2, utils::HashSet};
+use rand::{prelude::StdRng, Rng};
+use sark_grids::Grid;
+
+use crate::{
+    config::{BuildStep, MapGenAlgorithm, MapGenSettings, StartPosition, Symmetry},
+    map::{ensure_spawn_region, generate_rooms, get_random_ivec, Map, MapGenAssets, MapTile},
+    shapes::Rect,
+};
+
+use super::{
+    floor_regions, generate_bsp_rooms, generate_caves, generate_walk, reachable_from, stamp_vault,
+    MapHistory, VaultGlyph, WalkKind,
+};
+
+/// Vaults are rare, a level never gets more than this many.
+const MAX_VAULTS_PER_LEVEL: usize = 2;
+
+/// Chance for each gap in a room's walls to get a door.
+const DOOR_CHANCE: f64 = 0.6;
+
+/// Chance for each room to get a patch of terrain.
+const TERRAIN_CHANCE: f64 = 0.4;
+
+/// Lava only shows up from this depth down.
+const LAVA_MIN_DEPTH: u32 = 3;
+
+/// A monster or item to spawn once the level is built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spawn {
+    /// The name of a monster or item template.
+    pub name: String,
+    pub position: IVec2,
+}
+
+/// A level as it's being built, passed along from step to step.
+pub struct BuildState {
+    pub map: Map,
+    /// Rooms, or spawn regions for generators that don't make rooms. Never
+    /// empty once the map is laid out.
+    pub rooms: Vec<Rect>,
+    /// Where the player starts, set by [BuildStep::Start].
+    pub start: Option<IVec2>,
+    pub monsters: Vec<Spawn>,
+    pub items: Vec<Spawn>,
+    /// Tiles already taken by stairs, vaults or spawns.
+    pub placed: HashSet<IVec2>,
+    pub depth: u32,
+    /// Snapshots of the map after each change, if enabled.
+    pub history: MapHistory,
+}
+
+impl BuildState {
+    pub fn new(size: [u32; 2], depth: u32) -> Self {
+        Self {
+            map: Map(Grid::default(size)),
+            rooms: Vec::with_capacity(50),
+            start: None,
+            monsters: Vec::new(),
+            items: Vec::new(),
+            placed: HashSet::default(),
+            depth,
+            history: MapHistory::default(),
+        }
+    }
+
+    /// The floor tile in the room closest to the target. Rooms from some
+    /// generators, like caves, aren't entirely floor.
+    pub fn floor_near(&self, room: &Rect, target: IVec2) -> IVec2 {
+        let distance = |p: &IVec2| (*p - target).abs().max_element();
+        room.iter()
+            .filter(|p| self.map.0[*p] == MapTile::Floor)
+            .min_by_key(distance)
+            .unwrap_or(target)
+    }
+
+    /// Record the map and spawn lists to the history.
+    pub fn snapshot(&mut self, label: &str) {
+        self.history.record_with_spawns(&self.map, label, &self.monsters, &self.items);
+    }
+
+    /// Where the player starts, the center of the first room if no step picked it.
+    pub fn start(&self) -> IVec2 {
+        self.start.unwrap_or_else(|| self.floor_near(&self.rooms[0], self.rooms[0].center()))
+    }
+}
+
+/// Build a level: lay out the map with the algorithm for the depth, then
+/// run each of the depth's build steps in order. If `record` is set every
+/// change to the map is kept in the state's history.
+pub fn build_level(
+    settings: &MapGenSettings,
+    assets: &MapGenAssets,
+    rng: &mut StdRng,
+    depth: u32,
+    record: bool,
+) -> BuildState {
+    let mut state = BuildState::new(settings.map_size, depth);
+    state.history.enabled = record;
+
+    lay_out(&mut state, settings.algorithm_at(depth), settings, rng);
+    // Every level needs somewhere to put the player
+    if state.rooms.is_empty() {
+        ensure_spawn_region(&mut state.map, &mut state.rooms);
+    }
+
+    for step in settings.steps_at(depth) {
+        run_step(&mut state, *step, settings, assets, rng);
+        if state.rooms.is_empty() {
+            ensure_spawn_region(&mut state.map, &mut state.rooms);
+        }
+        state.snapshot(&format!("{:?}", step));
+    }
+
+    state
+}
+
+/// Carve out the map with one of the initial builders.
+pub fn lay_out(state: &mut BuildState, algorithm: MapGenAlgorithm, settings: &MapGenSettings, rng: &mut StdRng) {
+    let map = &mut state.map;
+    let rooms = &mut state.rooms;
+    let history = &mut state.history;
+    match algorithm {
+        MapGenAlgorithm::Rooms => generate_rooms(map, settings, rng, rooms, history),
+        MapGenAlgorithm::Bsp => generate_bsp_rooms(map, settings, rng, rooms, history),
+        MapGenAlgorithm::Caves => generate_caves(map, settings, rng, rooms, history),
+        MapGenAlgorithm::DrunkardsWalk => generate_walk(map, settings, rng, rooms, WalkKind::Drunkard, history),
+        MapGenAlgorithm::DlaWalkInwards => generate_walk(map, settings, rng, rooms, WalkKind::WalkInwards, history),
+        MapGenAlgorithm::DlaWalkOutwards => generate_walk(map, settings, rng, rooms, WalkKind::WalkOutwards, history),
+        MapGenAlgorithm::DlaCentralAttractor => generate_walk(map, settings, rng, rooms, WalkKind::CentralAttractor, history),
+    }
+}
+
+pub fn run_step(
+    state: &mut BuildState,
+    step: BuildStep,
+    settings: &MapGenSettings,
+    assets: &MapGenAssets,
+    rng: &mut StdRng,
+) {
+    match step {
+        BuildStep::CullUnreachable => cull_unreachable(state),
+        BuildStep::Symmetry(symmetry) => {
+            if symmetry != Symmetry::Vertical {
+                mirror(state, 0);
+            }
+            if symmetry != Symmetry::Horizontal {
+                mirror(state, 1);
+            }
+        },
+        BuildStep::Start(position) => pick_start(state, position, rng),
+        BuildStep::Vaults => place_vaults(state, assets, rng),
+        BuildStep::Doors => place_doors(state, rng),
+        BuildStep::Terrain => place_terrain(state, rng),
+        BuildStep::Stairs => place_stairs(state),
+        BuildStep::Spawn => roll_spawns(state, settings, assets, rng),
+    }
+}
+
+/// Wall off everything that isn't connected to the start.
+fn cull_unreachable(state: &mut BuildState) {
+    let regions = floor_regions(&state.map);
+    let keep = match state.start {
+        Some(start) => regions.iter().position(|r| r.contains(&start)).unwrap_or(0),
+        None => 0,
+    };
+
+    for (i, region) in regions.iter().enumerate() {
+        if i != keep {
+            for p in region {
+                state.map.0[*p] = MapTile::Wall;
+            }
+        }
+    }
+
+    let map = &state.map;
+    state.rooms.retain(|r| r.iter().any(|p| map.0[p] == MapTile::Floor));
+    state.monsters.retain(|s| map.0[s.position].is_walkable());
+    state.items.retain(|s| map.0[s.position].is_walkable());
+}
+
+/// Copy the low half of the map onto the high half along an axis, 0 for x
+/// and 1 for y, then join each area to its copy if they don't touch.
+///
+/// Areas that were only connected through the high half stay apart, follow
+/// with [BuildStep::CullUnreachable] to remove them.
+fn mirror(state: &mut BuildState, axis: usize) {
+    let size = state.map.0.size().as_ivec2();
+    let len = size[axis];
+    let half = len / 2;
+    let flip = |p: IVec2| {
+        let mut flipped = p;
+        flipped[axis] = len - 1 - p[axis];
+        flipped
+    };
+
+    for i in 0..state.map.0.len() {
+        let p = state.map.0.index_to_pos(i);
+        if p[axis] >= len - half {
+            state.map.0[i] = state.map.0[flip(p)];
+        }
+    }
+
+    // Join each area in the low half to its copy with a straight tunnel
+    // across the middle, which keeps the map symmetrical
+    for region in floor_regions(&state.map) {
+        let p = match region.iter().max_by_key(|p| p[axis]) {
+            Some(p) if p[axis] < half => *p,
+            _ => continue,
+        };
+        let other = flip(p);
+        if reachable_from(&state.map, p)[other] {
+            continue;
+        }
+        for t in p[axis]..=other[axis] {
+            let mut tile = p;
+            tile[axis] = t;
+            if state.map.0[tile] == MapTile::Wall {
+                state.map.0[tile] = MapTile::Floor;
+            }
+        }
+    }
+
+    // Rooms in the low half are copied, rooms in the high half were covered
+    // up. Rooms reaching across the middle are cut off at it so they don't
+    // overlap their copy
+    state.rooms.retain(|room| room.min[axis] < half);
+    let mut mirrored = Vec::with_capacity(state.rooms.len());
+    for room in state.rooms.iter_mut() {
+        room.max[axis] = room.max[axis].min(half);
+        let mut min = room.min;
+        let mut max = room.max;
+        min[axis] = len - room.max[axis];
+        max[axis] = len - room.min[axis];
+        mirrored.push(Rect::from_extents(min.into(), max.into()));
+    }
+    state.rooms.extend(mirrored);
+}
+
+/// Move the room the player starts in to the front of the room list.
+fn pick_start(state: &mut BuildState, position: StartPosition, rng: &mut StdRng) {
+    let center = state.map.0.size().as_ivec2() / 2;
+    let i = match position {
+        StartPosition::FirstRoom => 0,
+        StartPosition::Center => state
+            .rooms
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, r)| (r.center() - center).abs().max_element())
+            .map_or(0, |(i, _)| i),
+        StartPosition::RandomRoom => rng.gen_range(0..state.rooms.len()),
+    };
+    state.rooms.swap(0, i);
+    state.start = Some(state.floor_near(&state.rooms[0], state.rooms[0].center()));
+}
+
+/// Stamp vaults for this depth into the map and add what's in them to the
+/// spawn lists.
+///
+/// Vaults stay clear of the first and last rooms so they can't cover the
+/// player or the stairs. Nothing else is placed inside a vault.
+fn place_vaults(state: &mut BuildState, assets: &MapGenAssets, rng: &mut StdRng) {
+    let start = state.start();
+    let legend = &assets.vaults.legend;
+    let mut stamped: Vec<Rect> = Vec::new();
+
+    for vault in assets.vaults.vaults.iter() {
+        if stamped.len() >= MAX_VAULTS_PER_LEVEL {
+            break;
+        }
+        if state.depth < vault.min_depth || !rng.gen_bool(vault.chance) {
+            continue;
+        }
+
+        let mut avoid: Vec<&Rect> = stamped.iter().collect();
+        avoid.push(&state.rooms[0]);
+        avoid.push(&state.rooms[state.rooms.len() - 1]);
+
+        let area = match stamp_vault(&mut state.map, vault, legend, rng, start, &avoid) {
+            Some(area) => area,
+            None => continue,
+        };
+
+        let inside = |p: IVec2| p.cmpge(area.min).all() && p.cmplt(area.max).all();
+        state.monsters.retain(|s| !inside(s.position));
+        state.items.retain(|s| !inside(s.position));
+
+        for (p, c) in vault.iter() {
+            let position = area.min + p;
+            match legend.get(&c) {
+                Some(VaultGlyph::Monster(name)) => state.monsters.push(Spawn { name: name.clone(), position }),
+                Some(VaultGlyph::Item(name)) => state.items.push(Spawn { name: name.clone(), position }),
+                _ => {},
+            }
+        }
+        state.placed.extend(area.iter());
+        stamped.push(area);
+    }
+}
+
+/// Put closed doors in gaps in the walls around rooms.
+fn place_doors(state: &mut BuildState, rng: &mut StdRng) {
+    let mut gaps = Vec::new();
+    for room in state.rooms.iter() {
+        // The ring of tiles around the room, where its walls are
+        let ring = Rect {
+            min: room.min - IVec2::ONE,
+            max: room.max + IVec2::ONE,
+        };
+        for p in ring.iter() {
+            let on_ring = p.x == ring.min.x || p.x == ring.max.x - 1 || p.y == ring.min.y || p.y == ring.max.y - 1;
+            if on_ring && !state.placed.contains(&p) && is_doorway(&state.map, p) {
+                gaps.push(p);
+            }
+        }
+    }
+
+    for p in gaps {
+        // Doors side by side would only need one of them opened
+        let beside_door = [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y]
+            .iter()
+            .any(|dir| state.map.0[p + *dir] == MapTile::ClosedDoor);
+        if !beside_door && rng.gen_bool(DOOR_CHANCE) {
+            state.map.0[p] = MapTile::ClosedDoor;
+            state.placed.insert(p);
+        }
+    }
+}
+
+/// A floor tile with walls on two opposite sides and a way through on the others.
+fn is_doorway(map: &Map, p: IVec2) -> bool {
+    let size = map.0.size().as_ivec2();
+    if p.cmplt(IVec2::ONE).any() || p.cmpge(size - IVec2::ONE).any() || map.0[p] != MapTile::Floor {
+        return false;
+    }
+
+    let wall = |dir: IVec2| map.0[p + dir] == MapTile::Wall;
+    let open = |dir: IVec2| map.0[p + dir].is_passable();
+    (wall(IVec2::X) && wall(-IVec2::X) && open(IVec2::Y) && open(-IVec2::Y))
+        || (wall(IVec2::Y) && wall(-IVec2::Y) && open(IVec2::X) && open(-IVec2::X))
+}
+
+/// Put a patch of grass, rubble, water or lava in some of the rooms. Water
+/// is deep in the middle of larger patches.
+///
+/// Patches only cover bare floor and the player's room is left alone. A
+/// patch that would cut off part of the level, or leave lava as the only
+/// way through, is taken back out.
+fn place_terrain(state: &mut BuildState, rng: &mut StdRng) {
+    let start = state.start();
+
+    for i in 1..state.rooms.len() {
+        if !rng.gen_bool(TERRAIN_CHANCE) {
+            continue;
+        }
+
+        let tile = match rng.gen_range(0..10) {
+            0..=3 => MapTile::Grass,
+            4..=6 => MapTile::ShallowWater,
+            9 if state.depth >= LAVA_MIN_DEPTH => MapTile::Lava,
+            _ => MapTile::Rubble,
+        };
+        let (min, max) = (state.rooms[i].min, state.rooms[i].max);
+        let center = get_random_ivec(rng, min, max);
+        let radius: i32 = rng.gen_range(1..=3);
+
+        let before = safely_reachable(&state.map, start);
+        let mut patch = Vec::new();
+        for p in state.rooms[i].iter() {
+            let distance = (p - center).dot(p - center);
+            if distance > radius * radius
+                || p == start
+                || state.placed.contains(&p)
+                || state.map.0[p] != MapTile::Floor
+            {
+                continue;
+            }
+            let deep = tile == MapTile::ShallowWater && distance < (radius - 1) * (radius - 1);
+            state.map.0[p] = if deep { MapTile::DeepWater } else { tile };
+            patch.push(p);
+        }
+
+        let after = safely_reachable(&state.map, start);
+        let cut_off = (0..state.map.0.len()).any(|t| {
+            let tile = state.map.0[t];
+            before[t] && !after[t] && tile.is_passable() && tile.properties().on_enter.is_none()
+        });
+        if cut_off {
+            for p in patch {
+                state.map.0[p] = MapTile::Floor;
+            }
+        }
+    }
+}
+
+/// Every tile that can be walked to from `start` without stepping on
+/// anything harmful, like lava.
+fn safely_reachable(map: &Map, start: IVec2) -> Grid<bool> {
+    let mut safe = Map(Grid::default(map.0.size()));
+    for i in 0..map.0.len() {
+        if map.0[i].properties().on_enter.is_none() {
+            safe.0[i] = map.0[i];
+        }
+    }
+    reachable_from(&safe, start)
+}
+
+/// Place the down stairs in the last room. Below the first level the up
+/// stairs are placed where the player starts.
+fn place_stairs(state: &mut BuildState) {
+    let start = state.start();
+    if state.depth > 1 {
+        state.map.0[start] = MapTile::UpStairs;
+    }
+
+    let last = &state.rooms[state.rooms.len() - 1];
+    let down = match state.rooms.len() {
+        // Don't put the stairs under the player
+        1 => state.floor_near(last, last.min),
+        _ => state.floor_near(last, last.center()),
+    };
+    state.map.0[down] = MapTile::DownStairs;
+    state.placed.insert(down);
+}
+
+/// Pick random monsters and items for each room.
+fn roll_spawns(state: &mut BuildState, settings: &MapGenSettings, assets: &MapGenAssets, rng: &mut StdRng) {
+    let depth = state.depth;
+    // Deeper levels get more monsters
+    let extra = depth.saturating_sub(1) / 2;
+
+    // The first room is the player's room
+    'monsters: for room in state.rooms.iter().skip(1) {
+        let count = rng.gen_range(settings.monsters_per_room.clone()) + extra;
+
+        for _ in 0..=count {
+            for _ in 0..2 {
+                // If the first try fails, try again
+                let p = get_random_ivec(rng, room.min, room.max);
+
+                if state.placed.contains(&p) || state.map.0[p] != MapTile::Floor {
+                    continue;
+                }
+
+                let template = match assets.monsters.random_for_depth(rng, depth) {
+                    Some(template) => template,
+                    None => break 'monsters,
+                };
+                state.placed.insert(p);
+                state.monsters.push(Spawn { name: template.name.clone(), position: p });
+
+                break;
+            }
+        }
+    }
+
+    'items: for room in state.rooms.iter() {
+        let count = rng.gen_range(settings.items_per_room.clone());
+
+        for _ in 0..count {
+            let p = get_random_ivec(rng, room.min, room.max);
+
+            if state.placed.contains(&p) || state.map.0[p] != MapTile::Floor {
+                continue;
+            }
+
+            let template = match assets.items.random_for_depth(rng, depth) {
+                Some(template) => template,
+                None => break 'items,
+            };
+            state.placed.insert(p);
+            state.items.push(Spawn { name: template.name.clone(), position: p });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+
+    use crate::{inventory::ItemTemplates, map_gen::{MapStats, Vaults}, monster::MonsterTemplates};
+
+    use super::*;
+
+    fn build(settings: &MapGenSettings, seed: u64, depth: u32) -> BuildState {
+        let monsters = MonsterTemplates::default();
+        let items = ItemTemplates::default();
+        let vaults = Vaults::default();
+        let assets = MapGenAssets {
+            monsters: &monsters,
+            items: &items,
+            vaults: &vaults,
+        };
+        build_level(settings, &assets, &mut StdRng::seed_from_u64(seed), depth, true)
+    }
+
+    #[test]
+    fn default_steps() {
+        let state = build(&MapGenSettings::default(), 3, 2);
+
+        assert_eq!(Some(MapTile::UpStairs), state.start.map(|p| state.map.0[p]));
+        assert_eq!(1, state.map.0.iter().filter(|t| **t == MapTile::DownStairs).count());
+        assert!(!state.monsters.is_empty());
+        for spawn in state.monsters.iter() {
+            assert_eq!(MapTile::Floor, state.map.0[spawn.position]);
+        }
+    }
+
+    #[test]
+    fn history_records_each_step() {
+        let state = build(&MapGenSettings::default(), 3, 1);
+        let labels: Vec<&str> = state.history.snapshots.iter().map(|s| s.label.as_str()).collect();
+
+        assert!(labels.contains(&"Room"));
+        assert!(labels.contains(&"Tunnel"));
+        assert_eq!(
+            &["Start(FirstRoom)", "Vaults", "Doors", "Terrain", "Stairs", "Spawn"],
+            &labels[labels.len() - 6..]
+        );
+
+        let last = state.history.snapshots.last().unwrap();
+        assert!(last.tiles.iter().eq(state.map.0.iter()));
+        assert_eq!(state.monsters, last.monsters);
+    }
+
+    #[test]
+    fn symmetry_mirrors_map() {
+        let settings = MapGenSettings {
+            algorithm: MapGenAlgorithm::Caves,
+            steps: vec![BuildStep::Symmetry(Symmetry::Horizontal), BuildStep::CullUnreachable],
+            ..Default::default()
+        };
+
+        for seed in 0..5 {
+            let state = build(&settings, seed, 1);
+            let size = state.map.0.size().as_ivec2();
+            for i in 0..state.map.0.len() {
+                let p = state.map.0.index_to_pos(i);
+                let flipped = IVec2::new(size.x - 1 - p.x, p.y);
+                assert_eq!(state.map.0[p], state.map.0[flipped]);
+            }
+            assert_eq!(1, floor_regions(&state.map).len());
+        }
+    }
+
+    #[test]
+    fn mirrored_rooms_stay_in_their_half() {
+        let settings = MapGenSettings {
+            algorithm: MapGenAlgorithm::Bsp,
+            steps: vec![BuildStep::Symmetry(Symmetry::Both)],
+            ..Default::default()
+        };
+
+        for seed in 0..5 {
+            let state = build(&settings, seed, 1);
+            let size = state.map.0.size().as_ivec2();
+            let half = size / 2;
+            for room in state.rooms.iter() {
+                for axis in 0..2 {
+                    assert!(room.max[axis] <= half[axis] || room.min[axis] >= size[axis] - half[axis]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn doors_fill_gaps_in_room_walls() {
+        let mut doors = 0;
+        for seed in 0..5 {
+            let state = build(&MapGenSettings::default(), seed, 1);
+            for i in 0..state.map.0.len() {
+                if state.map.0[i] == MapTile::ClosedDoor {
+                    doors += 1;
+                    let p = state.map.0.index_to_pos(i);
+                    let wall = |dir: IVec2| state.map.0[p + dir] == MapTile::Wall;
+                    assert!((wall(IVec2::X) && wall(-IVec2::X)) || (wall(IVec2::Y) && wall(-IVec2::Y)));
+                }
+            }
+            // Doors don't cut anything off
+            assert!(MapStats::new(&state).fully_connected());
+        }
+        assert!(doors > 0);
+    }
+
+    #[test]
+    fn terrain_keeps_level_connected() {
+        let mut terrain = Vec::new();
+        for seed in 0..5 {
+            let state = build(&MapGenSettings::default(), seed, LAVA_MIN_DEPTH);
+            terrain.extend(state.map.0.iter().copied().filter(|t| {
+                !matches!(t, MapTile::Wall | MapTile::Floor | MapTile::ClosedDoor | MapTile::UpStairs | MapTile::DownStairs)
+            }));
+
+            assert!(MapStats::new(&state).fully_connected());
+            // Nothing is only reachable across lava
+            let safe = safely_reachable(&state.map, state.start());
+            for i in 0..state.map.0.len() {
+                let tile = state.map.0[i];
+                if tile.is_passable() && tile != MapTile::Lava {
+                    assert!(safe[i]);
+                }
+            }
+        }
+        assert!(terrain.contains(&MapTile::Grass));
+        assert!(terrain.contains(&MapTile::ShallowWater));
+    }
+
+    #[test]
+    fn cull_unreachable_keeps_start() {
+        let mut state = BuildState::new([20, 10], 1);
+        for x in 2..6 {
+            state.map.0[[x, 2]] = MapTile::Floor;
+        }
+        for x in 10..18 {
+            state.map.0[[x, 5]] = MapTile::Floor;
+        }
+        state.rooms.push(Rect::from_position_size((2, 2), (4, 1)));
+        state.rooms.push(Rect::from_position_size((10, 5), (8, 1)));
+        state.start = Some(IVec2::new(3, 2));
+
+        cull_unreachable(&mut state);
+
+        assert_eq!(MapTile::Floor, state.map.0[[3, 2]]);
+        assert_eq!(MapTile::Wall, state.map.0[[12, 5]]);
+        assert_eq!(1, state.rooms.len());
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt::Display;
+
+use super::{pipeline::BuildState, reachable_from};
+
+/// Numbers for judging a generated level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapStats {
+    /// How much of the map inside the border is walkable, from 0 to 100.
+    pub floor_percent: f32,
+    /// Rooms, or spawn regions for generators that don't make rooms.
+    pub rooms: usize,
+    /// How much of the walkable map can be reached from the start, from 0 to 100.
+    pub reachable_percent: f32,
+    pub monsters: usize,
+    pub items: usize,
+}
+
+impl MapStats {
+    pub fn new(state: &BuildState) -> Self {
+        let map = &state.map;
+        let size = map.0.size();
+        let interior = (size.x.saturating_sub(2) * size.y.saturating_sub(2)).max(1);
+
+        let open = map.0.iter().filter(|t| t.is_passable()).count();
+        let reached = reachable_from(map, state.start());
+        let reachable = reached.iter().filter(|r| **r).count();
+
+        Self {
+            floor_percent: open as f32 / interior as f32 * 100.0,
+            rooms: state.rooms.len(),
+            reachable_percent: match open {
+                0 => 0.0,
+                _ => reachable as f32 / open as f32 * 100.0,
+            },
+            monsters: state.monsters.len(),
+            items: state.items.len(),
+        }
+    }
+
+    /// Whether every walkable tile can be reached from the start.
+    pub fn fully_connected(&self) -> bool {
+        self.reachable_percent >= 100.0
+    }
+}
+
+impl Display for MapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "floor {:.1}%, rooms {}, reachable {:.1}%, monsters {}, items {}",
+            self.floor_percent, self.rooms, self.reachable_percent, self.monsters, self.items
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::IVec2;
+
+    use crate::{map::build_room, shapes::Rect};
+
+    use super::*;
+
+    #[test]
+    fn unreachable_floor() {
+        let mut state = BuildState::new([12, 12], 1);
+        build_room(&mut state.map, &Rect::from_position_size((1, 1), (5, 10)));
+        state.rooms.push(Rect::from_position_size((1, 1), (5, 10)));
+        state.start = Some(IVec2::new(2, 2));
+
+        let stats = MapStats::new(&state);
+        assert_eq!(50.0, stats.floor_percent);
+        assert!(stats.fully_connected());
+
+        build_room(&mut state.map, &Rect::from_position_size((7, 1), (4, 10)));
+        let stats = MapStats::new(&state);
+        assert!(!stats.fully_connected());
+        assert_eq!(1, stats.rooms);
+    }
+}
@@ -0,0 +1,398 @@
+use std::{collections::HashMap, fs::read_to_string};
+
+use bevy::math::IVec2;
+use rand::{prelude::StdRng, Rng};
+use sark_grids::Grid;
+use serde::Deserialize;
+
+use crate::{
+    config,
+    inventory::ItemTemplates,
+    map::{Map, MapTile},
+    monster::MonsterTemplates,
+    shapes::Rect,
+};
+
+use super::reachable_from;
+
+pub const VAULTS_FILE_NAME: &str = "vaults.ron";
+
+/// Random spots tried for each vault before giving up on it.
+const VAULT_ATTEMPTS: u32 = 30;
+
+/// What a character in a vault's text file becomes.
+#[derive(Debug, Clone, Deserialize)]
+pub enum VaultGlyph {
+    Tile(MapTile),
+    /// A monster from `monsters.ron`, standing on floor.
+    Monster(String),
+    /// An item from `items.ron`, lying on floor.
+    Item(String),
+}
+
+impl VaultGlyph {
+    pub fn tile(&self) -> MapTile {
+        match self {
+            VaultGlyph::Tile(tile) => *tile,
+            _ => MapTile::Floor,
+        }
+    }
+}
+
+/// An entry in [VAULTS_FILE_NAME].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VaultDef {
+    name: String,
+    /// Text file in `assets/vaults`.
+    file: String,
+    #[serde(default = "default_min_depth")]
+    min_depth: u32,
+    /// Chance for the vault to appear on each level, from 0.0 to 1.0.
+    chance: f64,
+}
+
+fn default_min_depth() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VaultsFile {
+    legend: HashMap<char, VaultGlyph>,
+    vaults: Vec<VaultDef>,
+}
+
+/// A hand made room stamped into generated maps.
+#[derive(Debug, Clone)]
+pub struct Vault {
+    pub name: String,
+    pub min_depth: u32,
+    pub chance: f64,
+    pub size: IVec2,
+    /// One character per tile, bottom row first.
+    glyphs: Vec<char>,
+}
+
+impl Vault {
+    /// Parse a vault from its text. The first line is the top of the vault.
+    pub fn parse(name: &str, text: &str) -> Result<Vault, String> {
+        let rows: Vec<Vec<char>> = text
+            .lines()
+            .map(|l| l.trim_end_matches('\r'))
+            .filter(|l| !l.is_empty())
+            .map(|l| l.chars().collect())
+            .collect();
+
+        let width = rows.first().map_or(0, |r| r.len());
+        if width == 0 {
+            return Err(format!("vault '{}' is empty", name));
+        }
+        if rows.iter().any(|r| r.len() != width) {
+            return Err(format!("every line of vault '{}' must be the same length", name));
+        }
+
+        Ok(Vault {
+            name: name.to_string(),
+            min_depth: 1,
+            chance: 1.0,
+            size: IVec2::new(width as i32, rows.len() as i32),
+            glyphs: rows.into_iter().rev().flatten().collect(),
+        })
+    }
+
+    /// Every position inside the vault, from the bottom left, along with its character.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, char)> + '_ {
+        let w = self.size.x;
+        self.glyphs.iter().enumerate().map(move |(i, c)| (IVec2::new(i as i32 % w, i as i32 / w), *c))
+    }
+
+    fn is_edge(&self, p: IVec2) -> bool {
+        p.x == 0 || p.y == 0 || p.x == self.size.x - 1 || p.y == self.size.y - 1
+    }
+}
+
+/// Every vault and the legend for reading them, from [VAULTS_FILE_NAME].
+#[derive(Debug, Clone, Default)]
+pub struct Vaults {
+    pub legend: HashMap<char, VaultGlyph>,
+    pub vaults: Vec<Vault>,
+}
+
+impl Vaults {
+    /// Check that every vault can be read with the legend and has a way in.
+    pub fn validate(&self) -> Result<(), String> {
+        for vault in self.vaults.iter() {
+            if !(0.0..=1.0).contains(&vault.chance) {
+                return Err(format!("vault '{}' has a chance of {}, it must be from 0.0 to 1.0", vault.name, vault.chance));
+            }
+
+            let mut entrances = 0;
+            for (p, c) in vault.iter() {
+                let glyph = match self.legend.get(&c) {
+                    Some(glyph) => glyph,
+                    None => return Err(format!("vault '{}' uses '{}' which isn't in the legend", vault.name, c)),
+                };
+                if vault.is_edge(p) && glyph.tile().is_passable() {
+                    entrances += 1;
+                }
+            }
+            if entrances == 0 {
+                return Err(format!("vault '{}' needs an opening on its edge", vault.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the monsters and items in the legend exist.
+    pub fn validate_spawns(&self, monsters: &MonsterTemplates, items: &ItemTemplates) -> Result<(), String> {
+        for (c, glyph) in self.legend.iter() {
+            match glyph {
+                VaultGlyph::Monster(name) if monsters.get(name).is_none() => {
+                    return Err(format!("'{}' is a monster named '{}' but there is no such monster", c, name));
+                },
+                VaultGlyph::Item(name) if items.get(name).is_none() => {
+                    return Err(format!("'{}' is an item named '{}' but there is no such item", c, name));
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    pub fn try_load() -> Result<Vaults, String> {
+        let file: VaultsFile = config::load_asset(VAULTS_FILE_NAME)?;
+
+        let mut vaults = Vec::new();
+        for def in file.vaults.iter() {
+            let path = config::asset_path(&format!("vaults/{}", def.file));
+            let text = match read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => return Err(format!("Error reading vault {}: {}", path.display(), e)),
+            };
+            let mut vault = Vault::parse(&def.name, &text)?;
+            vault.min_depth = def.min_depth;
+            vault.chance = def.chance;
+            vaults.push(vault);
+        }
+
+        let vaults = Vaults {
+            legend: file.legend,
+            vaults,
+        };
+        if let Err(e) = vaults.validate() {
+            return Err(format!("Invalid vaults in {}: {}", VAULTS_FILE_NAME, e));
+        }
+
+        Ok(vaults)
+    }
+
+    /// Read the vaults from the assets folder. There are no vaults if they can't be loaded.
+    pub fn load() -> Vaults {
+        match Vaults::try_load() {
+            Ok(vaults) => vaults,
+            Err(e) => {
+                eprintln!("{}. Levels will have no vaults.", e);
+                Vaults::default()
+            }
+        }
+    }
+}
+
+/// Try to stamp a vault into the map at a random spot where it fits.
+///
+/// The vault can't overlap any of the `avoid` areas. Spots where the vault
+/// would cut off part of the level reachable from `start` are skipped, and
+/// a tunnel is dug to the vault's entrance if it isn't already reachable.
+/// Returns the area of the vault if it was placed.
+pub fn stamp_vault(
+    map: &mut Map,
+    vault: &Vault,
+    legend: &HashMap<char, VaultGlyph>,
+    rng: &mut StdRng,
+    start: IVec2,
+    avoid: &[&Rect],
+) -> Option<Rect> {
+    let size = map.0.size().as_ivec2();
+    // Stay off the edge of the map
+    let max = size - vault.size - IVec2::ONE;
+    if max.x < 1 || max.y < 1 {
+        return None;
+    }
+
+    let before = reachable_from(map, start);
+
+    for _ in 0..VAULT_ATTEMPTS {
+        let pos = IVec2::new(rng.gen_range(1..=max.x), rng.gen_range(1..=max.y));
+        let area = Rect::from_position_size(pos.into(), vault.size.into());
+        if avoid.iter().any(|r| area.overlaps(r)) {
+            continue;
+        }
+
+        let old: Vec<(IVec2, MapTile)> = area.iter().map(|p| (p, map.0[p])).collect();
+        for (p, c) in vault.iter() {
+            map.0[pos + p] = legend.get(&c).map_or(MapTile::Wall, |g| g.tile());
+        }
+
+        let mut dug = Vec::new();
+        if connected(map, vault, pos, start, &before, &mut dug) {
+            return Some(area);
+        }
+
+        // Put everything back the way it was
+        for (p, tile) in old.into_iter().chain(dug.into_iter()) {
+            map.0[p] = tile;
+        }
+    }
+    None
+}
+
+/// Check the stamped vault didn't cut anything off, digging a tunnel to it
+/// if needed. Dug tiles are added to `dug` along with what they were before.
+fn connected(
+    map: &mut Map,
+    vault: &Vault,
+    pos: IVec2,
+    start: IVec2,
+    before: &Grid<bool>,
+    dug: &mut Vec<(IVec2, MapTile)>,
+) -> bool {
+    let area = Rect::from_position_size(pos.into(), vault.size.into());
+    let in_vault = |p: IVec2| p.cmpge(area.min).all() && p.cmplt(area.max).all();
+
+    let after = reachable_from(map, start);
+    let cut_off = (0..map.0.len()).any(|i| {
+        let p = map.0.index_to_pos(i);
+        before[i] && !after[i] && !in_vault(p)
+    });
+    if cut_off || !map.0[start].is_passable() {
+        return false;
+    }
+
+    let vault_reached = |reached: &Grid<bool>, map: &Map| {
+        vault.iter().all(|(p, _)| !map.0[pos + p].is_passable() || reached[pos + p])
+    };
+    if vault_reached(&after, map) {
+        return true;
+    }
+
+    // Dig from an entrance to the closest reachable tile
+    let entrance = vault.iter().map(|(p, _)| pos + p).find(|p| {
+        vault.is_edge(*p - pos) && map.0[*p].is_passable()
+    });
+    let entrance = match entrance {
+        Some(entrance) => entrance,
+        None => return false,
+    };
+
+    let path = match tunnel_to_reachable(map, entrance, &after, &in_vault) {
+        Some(path) => path,
+        None => return false,
+    };
+    for p in path {
+        if map.0[p] == MapTile::Wall {
+            dug.push((p, MapTile::Wall));
+            map.0[p] = MapTile::Floor;
+        }
+    }
+
+    let after = reachable_from(map, start);
+    vault_reached(&after, map)
+}
+
+/// The shortest path from the entrance to a reachable tile, not passing through the vault.
+fn tunnel_to_reachable(
+    map: &Map,
+    entrance: IVec2,
+    reachable: &Grid<bool>,
+    in_vault: &impl Fn(IVec2) -> bool,
+) -> Option<Vec<IVec2>> {
+    let size = map.0.size().as_ivec2();
+    let mut came_from: Vec<Option<IVec2>> = vec![None; map.0.len()];
+    let mut open = std::collections::VecDeque::new();
+    open.push_back(entrance);
+    came_from[map.0.pos_to_index(entrance)] = Some(entrance);
+
+    while let Some(p) = open.pop_front() {
+        if reachable[p] && map.0[p].is_passable() {
+            let mut path = vec![p];
+            let mut curr = p;
+            while curr != entrance {
+                curr = came_from[map.0.pos_to_index(curr)].unwrap();
+                path.push(curr);
+            }
+            return Some(path);
+        }
+
+        for dir in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+            let next = p + dir;
+            let interior = next.cmpge(IVec2::ONE).all() && next.cmplt(size - IVec2::ONE).all();
+            if !interior || in_vault(next) {
+                continue;
+            }
+            let i = map.0.pos_to_index(next);
+            if came_from[i].is_none() {
+                came_from[i] = Some(p);
+                open.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+
+    use crate::{map::build_room, map_gen::floor_regions};
+
+    use super::*;
+
+    #[test]
+    fn asset_vaults_are_valid() {
+        let vaults = Vaults::try_load().unwrap();
+        let monsters = MonsterTemplates::try_load().unwrap();
+        let items = ItemTemplates::try_load().unwrap();
+        vaults.validate_spawns(&monsters, &items).unwrap();
+    }
+
+    #[test]
+    fn parse_is_bottom_up() {
+        let vault = Vault::parse("test", "##.\n#g#\n").unwrap();
+        assert_eq!(IVec2::new(3, 2), vault.size);
+        let glyphs: Vec<(IVec2, char)> = vault.iter().collect();
+        assert_eq!((IVec2::new(1, 0), 'g'), glyphs[1]);
+        assert_eq!((IVec2::new(2, 1), '.'), glyphs[5]);
+
+        assert!(Vault::parse("test", "###\n##\n").is_err());
+    }
+
+    #[test]
+    fn stamp_keeps_map_connected() {
+        let legend: HashMap<char, VaultGlyph> = [
+            ('#', VaultGlyph::Tile(MapTile::Wall)),
+            ('.', VaultGlyph::Tile(MapTile::Floor)),
+        ].into_iter().collect();
+        let vault = Vault::parse("test", "#####\n#...#\n#...#\n##.##\n").unwrap();
+
+        for seed in 0..10 {
+            let mut map = Map(Grid::default([40, 20]));
+            let left = Rect::from_position_size((2, 2), (6, 6));
+            let right = Rect::from_position_size((30, 10), (6, 6));
+            build_room(&mut map, &left);
+            build_room(&mut map, &right);
+            for x in 4..33 {
+                map.0[[x, 4]] = MapTile::Floor;
+            }
+            for y in 4..13 {
+                map.0[[32, y]] = MapTile::Floor;
+            }
+
+            let mut rng = StdRng::seed_from_u64(seed);
+            let placed = stamp_vault(&mut map, &vault, &legend, &mut rng, left.center(), &[&left, &right]);
+
+            assert!(placed.is_some());
+            assert_eq!(1, floor_regions(&map).len(), "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use bevy::math::IVec2;
+use rand::{prelude::StdRng, Rng};
+
+use crate::{
+    config::{MapGenSettings, WalkSettings, WalkerSpawn},
+    map::{Map, MapTile},
+    shapes::{Line, Rect},
+};
+
+use super::{spawn_regions, MapHistory};
+
+/// Gives up on reaching the target floor percentage after this many walkers.
+const MAX_WALKERS: u32 = 5000;
+/// The map is recorded each time this fraction of the target floor is carved.
+const RECORD_FRACTION: usize = 20;
+
+const DIRECTIONS: [IVec2; 4] = [IVec2::X, IVec2::Y, IVec2::new(-1, 0), IVec2::new(0, -1)];
+
+/// How walkers carve out the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkKind {
+    /// Walkers carve every tile they step on.
+    Drunkard,
+    /// Walkers start anywhere and wander until they bump into floor, then
+    /// carve the tile they came from.
+    WalkInwards,
+    /// Walkers start on floor and wander until they step onto a wall, then carve it.
+    WalkOutwards,
+    /// Walkers start anywhere and head straight for the center until they reach
+    /// floor, then carve the tile they came from.
+    CentralAttractor,
+}
+
+/// How many floor tiles the walkers carve before stopping, `floor_percent`
+/// of the map's interior rounded down.
+fn floor_target(map: &Map, walk: &WalkSettings) -> usize {
+    let size = map.0.size().as_ivec2();
+    let interior = (size.x - 2) * (size.y - 2);
+    (interior as f32 * walk.floor_percent / 100.0) as usize
+}
+
+/// Carve the map with random walkers until enough of it is floor. The map
+/// is divided into spawn regions of roughly `room_size`, each is added to `rooms`.
+pub fn generate_walk(
+    map: &mut Map,
+    settings: &MapGenSettings,
+    rng: &mut StdRng,
+    rooms: &mut Vec<Rect>,
+    kind: WalkKind,
+    history: &mut MapHistory,
+) {
+    let walk = &settings.walk;
+    let size = map.0.size().as_ivec2();
+    let center = size / 2;
+
+    // Everything is connected to the center
+    let seed = Rect::from_position_size((center - IVec2::ONE).into(), (3, 3));
+    for p in seed.iter() {
+        map.0[p] = MapTile::Floor;
+    }
+
+    let target = floor_target(map, walk);
+    let record_every = (target / RECORD_FRACTION).max(1);
+    let mut next_record = record_every;
+
+    for _ in 0..MAX_WALKERS {
+        let floor = floor_count(map);
+        if floor >= next_record {
+            history.record(map, "Walkers");
+            next_record = floor + record_every;
+        }
+        if floor >= target {
+            break;
+        }
+
+        match kind {
+            WalkKind::Drunkard => {
+                let mut p = spawn_on_floor(map, rng, walk.spawn, center);
+                for _ in 0..walk.lifetime {
+                    map.0[p] = MapTile::Floor;
+                    p = step(p, random_direction(rng), size);
+                }
+            },
+            WalkKind::WalkInwards => {
+                let mut p = random_interior(rng, size);
+                for _ in 0..walk.lifetime {
+                    let next = step(p, random_direction(rng), size);
+                    if map.0[next] == MapTile::Floor {
+                        map.0[p] = MapTile::Floor;
+                        break;
+                    }
+                    p = next;
+                }
+            },
+            WalkKind::WalkOutwards => {
+                let mut p = spawn_on_floor(map, rng, walk.spawn, center);
+                for _ in 0..walk.lifetime {
+                    p = step(p, random_direction(rng), size);
+                    if map.0[p] == MapTile::Wall {
+                        map.0[p] = MapTile::Floor;
+                        break;
+                    }
+                }
+            },
+            WalkKind::CentralAttractor => {
+                let start = random_interior(rng, size);
+                let mut prev = start;
+                for p in Line::new(start, center).iter() {
+                    if map.0[p] == MapTile::Floor {
+                        map.0[prev] = MapTile::Floor;
+                        break;
+                    }
+                    prev = p;
+                }
+            },
+        }
+    }
+
+    spawn_regions(map, settings, rooms);
+}
+
+fn floor_count(map: &Map) -> usize {
+    map.0.iter().filter(|t| **t == MapTile::Floor).count()
+}
+
+fn random_direction(rng: &mut StdRng) -> IVec2 {
+    DIRECTIONS[rng.gen_range(0..DIRECTIONS.len())]
+}
+
+/// Take a step, staying off the edge of the map.
+fn step(p: IVec2, dir: IVec2, size: IVec2) -> IVec2 {
+    (p + dir).clamp(IVec2::ONE, size - IVec2::splat(2))
+}
+
+fn random_interior(rng: &mut StdRng, size: IVec2) -> IVec2 {
+    IVec2::new(rng.gen_range(1..size.x - 1), rng.gen_range(1..size.y - 1))
+}
+
+fn spawn_on_floor(map: &Map, rng: &mut StdRng, spawn: WalkerSpawn, center: IVec2) -> IVec2 {
+    match spawn {
+        WalkerSpawn::Center => center,
+        WalkerSpawn::RandomFloor => {
+            let floor: Vec<usize> = map.0.iter()
+                .enumerate()
+                .filter(|(_, t)| **t == MapTile::Floor)
+                .map(|(i, _)| i)
+                .collect();
+            map.0.index_to_pos(floor[rng.gen_range(0..floor.len())])
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{prelude::StdRng, SeedableRng};
+    use sark_grids::Grid;
+
+    use crate::{config::{MapGenSettings, WalkerSpawn}, map::Map, map_gen::floor_regions};
+
+    use super::*;
+
+    #[test]
+    fn walks_are_connected() {
+        let kinds = [
+            WalkKind::Drunkard,
+            WalkKind::WalkInwards,
+            WalkKind::WalkOutwards,
+            WalkKind::CentralAttractor,
+        ];
+        for kind in kinds {
+            for spawn in [WalkerSpawn::Center, WalkerSpawn::RandomFloor] {
+                let mut settings = MapGenSettings::default();
+                settings.walk.spawn = spawn;
+
+                let mut rng = StdRng::seed_from_u64(7);
+                let mut map = Map(Grid::default(settings.map_size));
+                let mut rooms = Vec::new();
+                generate_walk(&mut map, &settings, &mut rng, &mut rooms, kind, &mut MapHistory::default());
+
+                // Walkers stop at the target or after MAX_WALKERS, the fixed
+                // seed reaches the target for every kind
+                let (floor, target) = (floor_count(&map), floor_target(&map, &settings.walk));
+                assert!(floor >= target, "{:?} only carved {} of {} tiles", kind, floor, target);
+                assert_eq!(1, floor_regions(&map).len(), "{:?}", kind);
+                assert!(!rooms.is_empty());
+            }
+        }
+    }
+}
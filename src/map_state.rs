@@ -3,18 +3,21 @@ use sark_grids::Grid;
 use sark_pathfinding::*;
 
 use crate::{
-    map::{Map, MapTile}, movement::Position,
+    map::Map, movement::Position, render::RENDER_SYSTEM_LABEL,
 };
 
+/// Label for the system rebuilding [MapObstacles] and [MapActors]. Occurs in [CoreStage::Last].
 pub const UPDATE_MAP_STATE_SYSTEM_LABEL: &str = "update_map_state_system";
 
 pub struct MapStatePlugin;
 
 impl Plugin for MapStatePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(
+        // Runs last so actors despawned during the frame are seen as removed
+        app.add_system_to_stage(CoreStage::Last,
             update_map_state_system
                 .label(UPDATE_MAP_STATE_SYSTEM_LABEL)
+                .before(RENDER_SYSTEM_LABEL)
         )
         .init_resource::<MapObstacles>()
         .init_resource::<MapActors>();
@@ -35,44 +38,87 @@ impl std::ops::Deref for MapObstacles {
     }
 }
 
+impl MapObstacles {
+    /// The map's impassable tiles, without any actors.
+    pub fn from_map(map: &Map) -> Self {
+        let mut obstacles = Grid::default(map.0.size());
+        for (i, tile) in map.0.iter().enumerate() {
+            // Closed doors are opened by whoever walks into them
+            obstacles[i] = !tile.is_passable();
+        }
+        Self(obstacles)
+    }
+}
+
 #[derive(Component, Default)]
 pub struct MapActors(pub Grid<Option<Entity>>);
 
+/// Rebuild the map state when the map changes or blockers move, spawn or are
+/// removed.
 fn update_map_state_system(
-    q_moved_actors: Query<&Position, (With<PathBlocker>, Changed<Position>)>,
+    q_moved_actors: Query<(), (With<PathBlocker>, Changed<Position>)>,
+    removed: RemovedComponents<PathBlocker>,
     q_blockers: Query<(Entity, &Position), With<PathBlocker>>,
-    q_changed_map: Query<&Map, Changed<Map>>,
-    q_map: Query<&Map>,
-    mut blockers: ResMut<MapObstacles>,
-    mut entities: ResMut<MapActors>,
+    q_map: Query<(&Map, ChangeTrackers<Map>)>,
+    mut obstacles: ResMut<MapObstacles>,
+    mut actors: ResMut<MapActors>,
 ) {
+    let (map, map_changes) = match q_map.get_single() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
 
-    if q_moved_actors.is_empty() && q_changed_map.is_empty()
-    && !blockers.is_changed() && !entities.is_changed() {
+    let resized = obstacles.0.len() != map.0.len() || actors.0.len() != map.0.len();
+    let removed = removed.iter().next().is_some();
+    if q_moved_actors.is_empty() && !removed && !map_changes.is_changed() && !resized {
         return;
     }
 
-    if let Ok(map) = q_map.get_single() {
-        if blockers.0.len() != map.0.len() {
-            blockers.0 = Grid::default(map.0.size())
-        }
+    *obstacles = MapObstacles::from_map(map);
+    actors.0 = Grid::default(map.0.size());
+    for (entity, pos) in q_blockers.iter() {
+        let i = map.0.pos_to_index(pos.0);
+        obstacles.0[i] = true;
+        actors.0[i] = Some(entity);
+    }
+}
 
-        if entities.0.len() != map.0.len() {
-            entities.0 = Grid::default(map.0.size());
-        }
+#[cfg(test)]
+mod test {
+    use crate::{map::MapTile, test_util::map_app};
 
-        for (i, tile) in map.0.iter().enumerate() {
-            blockers.0[i] = *tile == MapTile::Wall;
-        }
+    use super::*;
 
-        // Clear entity state
-        for entry in entities.0.iter_mut() { 
-            *entry = None;
-        }
-        for (entity, pos) in q_blockers.iter() {
-            let i = map.0.pos_to_index(pos.0);
-            blockers.0[i] = true;
-            entities.0[i] = Some(entity);
+    fn floor() -> Map {
+        let mut map = Map(Grid::default([4, 1]));
+        for tile in map.0.iter_mut() {
+            *tile = MapTile::Floor;
         }
+        map
+    }
+
+    #[test]
+    fn blockers_fill_map_state() {
+        let mut app = map_app(floor());
+        app.add_plugin(MapStatePlugin);
+        let blocker = app.world.spawn().insert_bundle((PathBlocker, Position(IVec2::new(2, 0)))).id();
+        app.update();
+
+        assert!(app.world.resource::<MapObstacles>().0[[2, 0]]);
+        assert_eq!(Some(blocker), app.world.resource::<MapActors>().0[[2, 0]]);
+        assert!(!app.world.resource::<MapObstacles>().0[[1, 0]]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn despawned_blockers_are_cleared() {
+        let mut app = map_app(floor());
+        app.add_plugin(MapStatePlugin);
+        let blocker = app.world.spawn().insert_bundle((PathBlocker, Position(IVec2::new(2, 0)))).id();
+        app.update();
+        app.world.despawn(blocker);
+        app.update();
+
+        assert!(!app.world.resource::<MapObstacles>().0[[2, 0]]);
+        assert_eq!(None, app.world.resource::<MapActors>().0[[2, 0]]);
+    }
+}
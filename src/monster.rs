@@ -1,36 +1,35 @@
 use bevy::prelude::*;
 use bracket_random::prelude::{DiceType};
-use sark_pathfinding::*;
+use rand::Rng;
+use serde::Deserialize;
 
 use crate::{
-    bundle::MovingEntityBundle, map_state::{
-        PathBlocker, 
-        MapObstacles, 
-        MapActors
-    }, 
+    action::RESOLVE_ACTIONS_SYSTEM_LABEL,
+    ai::{monster_ai, AiSettings, IdleBehavior, MonsterAi, MONSTER_AI_SYSTEM_LABEL},
+    bundle::MovingEntityBundle, map_state::PathBlocker, 
     visibility::{
         MapView, 
         VIEW_SYSTEM_LABEL, 
         ViewRange
     }, 
-    turn_system::{
-        Energy, 
-        TakingATurn
-    }, 
     combat::{
         CombatantBundle, 
         HitPoints, 
         MaxHitPoints, 
         Defense, Strength, 
-        TargetEvent, 
-        ActorEffect, AttackDice
-    }, movement::Position, player::Player, rng::DiceRng};
+        AttackDice
+    }, config, spawn_table::{self, SpawnEntry}};
 
 pub struct MonstersPlugin;
 
 impl Plugin for MonstersPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(monster_ai.after(VIEW_SYSTEM_LABEL));
+        app.insert_resource(MonsterTemplates::load())
+        .add_system(monster_ai
+            .label(MONSTER_AI_SYSTEM_LABEL)
+            .after(VIEW_SYSTEM_LABEL)
+            .before(RESOLVE_ACTIONS_SYSTEM_LABEL)
+        );
     }
 }
 
@@ -48,99 +47,235 @@ pub struct MonsterBundle {
     pub blocker: PathBlocker,
     pub vision: MapView,
     pub view_range: ViewRange,
+    pub ai: MonsterAi,
 }
 
 impl MonsterBundle {
-    pub fn new_goblin() -> Self {
+    /// Make the monster tougher on deeper levels of the dungeon.
+    pub fn scale_to_depth(&mut self, depth: u32) {
+        let bonus = depth.saturating_sub(1) as i32;
+        let combat = &mut self.combatant_bundle;
+        combat.hp.0 += bonus * 2;
+        combat.max_hp.0 += bonus * 2;
+        combat.strength.0 += bonus / 2;
+        combat.defense.0 += bonus / 3;
+    }
+}
+
+/// A kind of monster, read from [MONSTERS_FILE_NAME].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonsterTemplate {
+    pub name: String,
+    pub glyph: char,
+    /// Foreground color as rgb, from 0.0 to 1.0.
+    pub color: (f32, f32, f32),
+    pub speed: i32,
+    pub hp: i32,
+    pub defense: i32,
+    pub strength: i32,
+    /// A dice string, ie: `"2d6+1"`.
+    pub attack: AttackDice,
+    pub view_range: u32,
+    /// Defaults to wandering and never fleeing.
+    #[serde(default)]
+    pub ai: AiSettings,
+}
+
+impl MonsterTemplate {
+    pub fn bundle(&self) -> MonsterBundle {
+        let (r, g, b) = self.color;
         MonsterBundle {
-            movable: MovingEntityBundle::new(Color::RED, 'g', 20),
+            movable: MovingEntityBundle::new(Color::rgb(r, g, b), self.glyph, self.speed),
             combatant_bundle: CombatantBundle {
-                hp: HitPoints(15),
-                max_hp: MaxHitPoints(15),
-                defense: Defense(0),
-                strength: Strength(1),
-                attack_dice: AttackDice(DiceType::new(1,4,0)),
+                hp: HitPoints(self.hp),
+                max_hp: MaxHitPoints(self.hp),
+                defense: Defense(self.defense),
+                strength: Strength(self.strength),
+                attack_dice: self.attack.clone(),
             },
             monster: Default::default(),
-            name: Name::new("Goblin"),
+            name: Name::new(self.name.clone()),
             blocker: Default::default(),
             vision: Default::default(),
-            view_range: ViewRange(4),
+            view_range: ViewRange(self.view_range),
+            ai: MonsterAi::new(self.ai),
         }
     }
+}
 
-    pub fn new_orc() -> Self {
-        Self {
-            movable: MovingEntityBundle::new(Color::RED, 'o', 15),
-            combatant_bundle: CombatantBundle {
-                hp: HitPoints(25),
-                max_hp: MaxHitPoints(25),
-                defense: Defense(1),
-                strength: Strength(3),
-                attack_dice: AttackDice(DiceType::new(2,6,0)),
-            },
-            monster: Default::default(),
-            name: Name::new("Orc"),
-            blocker: Default::default(),
-            vision: Default::default(),
-            view_range: ViewRange(4),
+/// Every kind of monster and how likely they are to spawn, read from [MONSTERS_FILE_NAME].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonsterTemplates {
+    pub monsters: Vec<MonsterTemplate>,
+    pub spawn_table: Vec<SpawnEntry>,
+}
+
+pub const MONSTERS_FILE_NAME: &str = "monsters.ron";
+
+impl MonsterTemplates {
+    pub fn get(&self, name: &str) -> Option<&MonsterTemplate> {
+        self.monsters.iter().find(|m| m.name == name)
+    }
+
+    /// Pick a random monster from the spawn table for the given depth.
+    pub fn random_for_depth(&self, rng: &mut impl Rng, depth: u32) -> Option<&MonsterTemplate> {
+        spawn_table::roll(&self.spawn_table, rng, depth).and_then(|name| self.get(name))
+    }
+
+    /// Check for values that can't be used in game.
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, monster) in self.monsters.iter().enumerate() {
+            if self.monsters[..i].iter().any(|m| m.name == monster.name) {
+                return Err(format!("there is more than one monster named '{}'", monster.name));
+            }
+            if monster.speed <= 0 {
+                return Err(format!("{} has a speed of {}, speed must be greater than 0", monster.name, monster.speed));
+            }
+            if monster.hp <= 0 {
+                return Err(format!("{} has {} hp, hp must be greater than 0", monster.name, monster.hp));
+            }
+            if let Err(e) = monster.ai.validate() {
+                return Err(format!("{} has invalid ai: {}", monster.name, e));
+            }
         }
+
+        spawn_table::validate(&self.spawn_table, |name| self.get(name).is_some())
     }
 
-    pub fn get_from_index(index: u32) -> MonsterBundle {
-        match index {
-            0 => MonsterBundle::new_goblin(),
-            1 => MonsterBundle::new_orc(),
-            _ => MonsterBundle::new_goblin(),
+    pub fn try_load() -> Result<MonsterTemplates, String> {
+        let templates: MonsterTemplates = config::load_asset(MONSTERS_FILE_NAME)?;
+
+        if let Err(e) = templates.validate() {
+            return Err(format!("Invalid monsters in {}: {}", MONSTERS_FILE_NAME, e));
         }
+
+        Ok(templates)
     }
 
-    pub fn max_index() -> u32 {
-        2
+    /// Read the monsters from the assets folder, falling back to the built in
+    /// goblins and orcs if they can't be loaded.
+    pub fn load() -> MonsterTemplates {
+        match MonsterTemplates::try_load() {
+            Ok(templates) => templates,
+            Err(e) => {
+                eprintln!("{}. Using default monsters.", e);
+                MonsterTemplates::default()
+            }
+        }
     }
 }
 
+impl Default for MonsterTemplates {
+    fn default() -> Self {
+        Self {
+            monsters: vec![
+                MonsterTemplate {
+                    name: "Goblin".to_string(),
+                    glyph: 'g',
+                    color: (1.0, 0.0, 0.0),
+                    speed: 20,
+                    hp: 15,
+                    defense: 0,
+                    strength: 1,
+                    attack: AttackDice(DiceType::new(1,4,0)),
+                    view_range: 4,
+                    ai: AiSettings {
+                        flee_below: 0.3,
+                        ..Default::default()
+                    },
+                },
+                MonsterTemplate {
+                    name: "Orc".to_string(),
+                    glyph: 'o',
+                    color: (1.0, 0.0, 0.0),
+                    speed: 15,
+                    hp: 25,
+                    defense: 1,
+                    strength: 3,
+                    attack: AttackDice(DiceType::new(2,6,0)),
+                    view_range: 4,
+                    ai: AiSettings {
+                        idle: IdleBehavior::Patrol { radius: 6 },
+                        ..Default::default()
+                    },
+                },
+            ],
+            spawn_table: vec![
+                SpawnEntry::new("Goblin", 10),
+                SpawnEntry::new("Orc", 10),
+            ],
+        }
+    }
+}
 
-fn monster_ai(
-    mut obstacles: ResMut<MapObstacles>,
-    mut entities: ResMut<MapActors>,
-    q_player: Query<(Entity, &Position), With<Player>>,
-    mut q_monster: Query<(Entity, &mut Position, &mut Energy, &AttackDice, &MapView, &Name), (With<Monster>, Without<Player>, With<TakingATurn>)>,
-    mut attack_events: EventWriter<TargetEvent>,
-    mut rng: Local<DiceRng>,
-) {
-    for (entity, mut pos, mut energy, dice, view, _name) in q_monster.iter_mut() {
-        let pos = &mut pos.0;
-        // Check if the player is in view.
-
-        if let Ok((player,player_pos)) = q_player.get_single() {
-            let player_pos = player_pos.0;
-            if view.0[player_pos] {
-                // Open the player and monster positions so pathfinding doesn't see them as obstacles
-                obstacles.0[*pos] = false;
-                obstacles.0[player_pos] = false;
-
-                let mut astar = AStar::new(5);
-                if let Some(path) = astar.find_path(&obstacles.0, *pos, player_pos) {
-                    if path.len() == 2 {
-                        let damage = rng.roll(dice.0);
-                        attack_events.send(TargetEvent {
-                            actor: entity,
-                            target: player,
-                            effect: ActorEffect::Damage(damage),
-                        });
-                    } else {
-                        entities.0[*pos] = None;
-                        *pos = path[1];
-                        entities.0[*pos] = Some(entity);
-                    }
-                }
-
-                obstacles.0[*pos] = true;
-                obstacles.0[player_pos] = true;
-            }
+#[cfg(test)]
+mod test {
+    use rand::{prelude::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn asset_monsters_are_valid() {
+        MonsterTemplates::try_load().unwrap();
+    }
+
+    #[test]
+    fn unknown_field() {
+        let result: Result<MonsterTemplate, _> = ron::from_str(r#"(
+            name: "Rat", glyph: 'r', color: (0.5, 0.5, 0.5), speed: 10, hp: 3,
+            defense: 0, strength: 0, attack: "1d2", view_range: 3, wings: 2,
+        )"#);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("wings"));
+    }
+
+    #[test]
+    fn ai_settings() {
+        let monster: MonsterTemplate = ron::from_str(r#"(
+            name: "Rat", glyph: 'r', color: (0.5, 0.5, 0.5), speed: 10, hp: 3,
+            defense: 0, strength: 0, attack: "1d2", view_range: 3,
+            ai: (idle: Patrol(radius: 4), flee_below: 0.5),
+        )"#).unwrap();
+        assert_eq!(IdleBehavior::Patrol { radius: 4 }, monster.ai.idle);
+        assert_eq!(AiSettings::default().search_turns, monster.ai.search_turns);
+    }
+
+    #[test]
+    fn bad_dice() {
+        let result: Result<MonsterTemplate, _> = ron::from_str(r#"(
+            name: "Rat", glyph: 'r', color: (0.5, 0.5, 0.5), speed: 10, hp: 3,
+            defense: 0, strength: 0, attack: "d6x", view_range: 3,
+        )"#);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("d6x"));
+    }
+
+    #[test]
+    fn spawn_entries_keep_the_monster_key() {
+        let old: SpawnEntry = ron::from_str(r#"( monster: "Goblin", weight: 2 )"#).unwrap();
+        let new: SpawnEntry = ron::from_str(r#"( name: "Goblin", weight: 2 )"#).unwrap();
+        assert_eq!("Goblin", old.name);
+        assert_eq!(old.name, new.name);
+    }
+
+    #[test]
+    fn spawn_weights() {
+        let mut templates = MonsterTemplates::default();
+        templates.spawn_table[1].min_depth = 3;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let monster = templates.random_for_depth(&mut rng, 1).unwrap();
+            assert_eq!("Goblin", monster.name);
         }
 
-        energy.0 = 0;
+        templates.spawn_table[0].weight_per_depth = -5;
+        assert_eq!(0, templates.spawn_table[0].weight_at(3));
+        for _ in 0..20 {
+            let monster = templates.random_for_depth(&mut rng, 3).unwrap();
+            assert_eq!("Orc", monster.name);
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,10 @@
 use bevy::prelude::*;
 
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Component for tracking entity positions on the map.
-#[derive(Component, Debug, Deserialize, Default)]
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize, Default)]
 pub struct Position(pub IVec2);
 
 /// Component for tracking entity movement.
@@ -1,15 +1,26 @@
 use bevy::prelude::*;
 
 use bracket_random::prelude::DiceType;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    action::{Action, ActionEvent},
     bundle::MovingEntityBundle,
-    map_state::{MapActors, MapObstacles},
+    map_state::MapActors,
     monster::Monster,
-    movement::{Movement, Position},
-    visibility::{MapMemory, MapView, ViewRange}, events::AttackEvent, turn_system::{TakingATurn, Energy}, combat::{CombatantBundle, HitPoints, MaxHitPoints, Defense, Strength, TargetEvent, ActorEffect, AttackDice}, rng::DiceRng,
+    movement::Position,
+    visibility::{MapMemory, MapView, ViewRange}, turn_system::TakingATurn, combat::{CombatantBundle, HitPoints, MaxHitPoints, Defense, Strength, AttackDice},
+    replay::ReplayPlayback,
+    map::Map,
+    inventory::Inventory,
+    equipment::{BaseStats, Equipment},
 };
 
+/// Label for the system reading player input. Occurs in [CoreStage::PreUpdate].
+pub const PLAYER_INPUT_SYSTEM_LABEL: &str = "player_input";
+
+pub const PLAYER_INVENTORY_CAPACITY: usize = 18;
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
@@ -17,8 +28,8 @@ impl Plugin for PlayerPlugin {
         app
         .add_startup_system_to_stage(StartupStage::PreStartup, spawn_player)
         //.add_startup_system(spawn_player.label(PLAYER_SETUP_LABEL))
-        .add_system_to_stage(CoreStage::PreUpdate, player_input);
-        
+        .init_resource::<InputMode>()
+        .add_system_to_stage(CoreStage::PreUpdate, player_input.label(PLAYER_INPUT_SYSTEM_LABEL));
     }
 }
 
@@ -42,83 +53,113 @@ pub struct PlayerBundle {
     pub name: Name,
     pub memory: MapMemory,
     pub view_range: ViewRange,
+    pub inventory: Inventory,
+    pub equipment: Equipment,
+    pub base_stats: BaseStats,
 }
 
 impl Default for PlayerBundle {
     fn default() -> Self {
+        let combatant_bundle = CombatantBundle {
+            hp: HitPoints(60),
+            max_hp: MaxHitPoints(60),
+            defense: Defense(1),
+            strength: Strength(3),
+            attack_dice: AttackDice(DiceType::new(5,3,0)),
+        };
         Self {
             move_bundle: MovingEntityBundle::new(Color::WHITE, '@', 25),
-            combatant_bundle: CombatantBundle {
-                hp: HitPoints(60),
-                max_hp: MaxHitPoints(60),
-                defense: Defense(1),
-                strength: Strength(3),
-                attack_dice: AttackDice(DiceType::new(5,3,0)),
-            },
+            base_stats: BaseStats::from(&combatant_bundle),
+            combatant_bundle,
             player: Default::default(),
             view: Default::default(),
             name: Name::new("Player"),
             memory: Default::default(),
             view_range: ViewRange(5),
-            
+            inventory: Inventory::new(PLAYER_INVENTORY_CAPACITY),
+            equipment: Default::default(),
         }
     }
 }
 
+/// What player input currently controls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Moving around the map.
+    Game,
+    /// Browsing the inventory screen.
+    Inventory,
+    /// Picking a target on the map, see [crate::targeting::Targeting].
+    Targeting,
+    /// Watching the map generator, see [crate::visualizer]. Turns are paused.
+    Visualizer,
+}
+
+impl Default for InputMode {
+    fn default() -> Self {
+        Self::Game
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn player_input(
-    mut q_player: Query<(Entity, &Strength, &mut Position, &mut Energy, &AttackDice, &mut Movement), (With<Player>, With<TakingATurn>)>,
+    q_player: Query<(Entity, &Position), (With<Player>, With<TakingATurn>)>,
     q_monsters: Query<&Name, With<Monster>>,
+    q_map: Query<&Map>,
     input: Res<Input<KeyCode>>,
-    mut obstacles: ResMut<MapObstacles>,
-    mut actors: ResMut<MapActors>,
-    _event_attack: EventWriter<AttackEvent>,
-    mut evt_attack: EventWriter<TargetEvent>,
-    mut rng: Local<DiceRng>,
+    actors: Res<MapActors>,
+    replay: Option<Res<ReplayPlayback>>,
+    mode: Res<InputMode>,
+    mut evt_action: EventWriter<ActionEvent>,
 ) {
-    if let Ok((entity, _attack, mut pos, mut energy, dice, mut movement)) = q_player.get_single_mut() {
+    // Actions come from the replay file instead
+    if replay.is_some() || *mode != InputMode::Game {
+        return;
+    }
+
+    if let Ok((player, pos)) = q_player.get_single() {
+        let mut act = |action| evt_action.send(ActionEvent { actor: player, action });
+
         if read_wait(&input) {
-            energy.0 = 0;
+            act(Action::Wait);
             return;
         }
 
-        let move_input = read_movement(&input);
-        if move_input.cmpeq(IVec2::ZERO).all() {
+        if input.just_pressed(KeyCode::Period) {
+            act(Action::Descend);
             return;
         }
 
-        let curr = IVec2::from(pos.0);
-
-        let next = curr + move_input;
-
-        let attack = rng.roll(dice.0);
+        if input.just_pressed(KeyCode::Comma) {
+            act(Action::Ascend);
+            return;
+        }
 
-        if obstacles.0[next] {
-            if let Some(target) = actors.0[next] {
-                if let Ok(_name) = q_monsters.get(target) {
-                    evt_attack.send( TargetEvent {
-                        actor: entity,
-                        target,
-                        effect: ActorEffect::Damage(attack),
-                    });
+        if input.just_pressed(KeyCode::G) {
+            act(Action::PickUp);
+            return;
+        }
 
-                    energy.0 = 0;
-                }
-            }
+        let move_input = read_movement(&input);
+        if move_input.cmpeq(IVec2::ZERO).all() {
             return;
         }
 
-        //println!("Player moved, ending their turn");
-        pos.0 = next.into();
-        energy.0 = 0;
-        actors.0[curr] = None;
-        actors.0[next] = Some(entity);
-        obstacles.0[curr] = false;
-        obstacles.0[next] = true;
-        movement.0 = move_input.into();
+        let next = pos.0 + move_input;
+        let is_monster = actors.0[next].map_or(false, |target| q_monsters.get(target).is_ok());
+        let is_door = q_map.get_single().map_or(false, |map| map.0[next].properties().opens_to.is_some());
+
+        if is_monster {
+            act(Action::Attack(move_input.into()));
+        } else if is_door {
+            act(Action::OpenDoor(move_input.into()));
+        } else {
+            act(Action::Move(move_input.into()));
+        }
     }
 }
 
-fn read_movement(input: &Input<KeyCode>) -> IVec2 {
+pub fn read_movement(input: &Input<KeyCode>) -> IVec2 {
     let mut p = IVec2::ZERO;
 
     if input.just_pressed(KeyCode::Numpad1) || input.just_pressed(KeyCode::Z) {
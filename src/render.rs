@@ -1,18 +1,25 @@
 use bevy::{ecs::schedule::ShouldRun, prelude::*};
 use bevy_ascii_terminal::{*, ui::BorderGlyphs};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     map::{Map, MapTile},
     movement::Position,
     player::Player,
     visibility::{MapMemory, MapView}, GameTerminal, combat::ActorKilledEvent,
+    turn_system::Actor,
+    player::InputMode,
+    targeting::{line_of_fire, Targeting},
+    map_state::MapActors,
+    combat::{HitPoints, MaxHitPoints},
+    camera::MapViewport,
 };
 
-pub const WALL_COLOR: Color = Color::Rgba{ red:0.866, green:0.866, blue:0.882, alpha: 1.0};
-pub const FLOOR_COLOR: Color = Color::Rgba{ red:0.602, green:0.462, blue:0.325, alpha: 1.0};
-
 pub const RENDER_SYSTEM_LABEL: &str = "GAME_RENDER_SYSTEM";
 
+pub const TARGET_PATH_COLOR: Color = Color::Rgba{ red:0.35, green:0.3, blue:0.05, alpha: 1.0};
+pub const TARGET_CURSOR_COLOR: Color = Color::Rgba{ red:0.8, green:0.65, blue:0.1, alpha: 1.0};
+
 /// Plugin managing game rendering systems
 pub struct RenderPlugin;
 impl Plugin for RenderPlugin {
@@ -23,13 +30,17 @@ impl Plugin for RenderPlugin {
             .with_system(
                 render
                 .label(RENDER_SYSTEM_LABEL)
+            )
+            .with_system(
+                render_targeting
+                .after(RENDER_SYSTEM_LABEL)
             ),
         )
         .add_plugin(TerminalPlugin);
     }
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct Renderable {
     pub fg_color: Color,
     pub bg_color: Color,
@@ -38,9 +49,11 @@ pub struct Renderable {
 
 fn render(
     q_map: Query<&Map>,
-    q_entities: Query<(&Renderable, &Position)>,
+    q_items: Query<(&Renderable, &Position), Without<Actor>>,
+    q_actors: Query<(&Renderable, &Position), With<Actor>>,
     q_player: Query<(Entity, &MapView), With<Player>>,
     q_memory: Query<&MapMemory>,
+    viewport: Res<MapViewport>,
     mut q_render_terminal: Query<&mut Terminal, With<GameTerminal>>,
 ) {
     let mut term = match q_render_terminal.get_single_mut() {
@@ -53,38 +66,30 @@ fn render(
         Err(_) => return,
     };
 
-    if term.size() != map.0.size() {
-        term.resize(map.0.size());
-    }
-
     term.clear();
 
+    // Actors are drawn last so they're drawn over any items they're standing on
+    let entities = q_items.iter().chain(q_actors.iter());
+
     if let Ok((entity, player_view)) = q_player.get_single() {
         if let Ok(memory) = q_memory.get(entity) {
-            render_memory(memory, map, &mut term);
+            render_memory(memory, map, &viewport, &mut term);
         }
-        render_view(player_view, &mut term, map, q_entities.iter());
+        render_view(player_view, &mut term, &viewport, map, entities);
     } else {
-        render_everything(map, &mut term, q_entities.iter());
+        render_everything(map, &mut term, &viewport, entities);
     }
 
     term.draw_border(BorderGlyphs::single_line());
 }
 
-// TODO: Should be handled by some kind of prefab/asset setup
 impl From<MapTile> for Tile {
     fn from(t: MapTile) -> Self {
-        match t {
-            MapTile::Wall => Tile {
-                glyph: '#',
-                fg_color: WALL_COLOR,
-                bg_color: Color::BLACK,
-            },
-            MapTile::Floor => Tile {
-                glyph: '.',
-                fg_color: FLOOR_COLOR,
-                bg_color: Color::BLACK,
-            },
+        let properties = t.properties();
+        Tile {
+            glyph: properties.glyph,
+            fg_color: properties.fg_color,
+            bg_color: properties.bg_color,
         }
     }
 }
@@ -99,27 +104,26 @@ impl From<&Renderable> for Tile {
     }
 }
 
-fn render_view<'a, Actors>(view: &MapView, term: &mut Terminal, map: &Map, actors: Actors)
+fn render_view<'a, Actors>(view: &MapView, term: &mut Terminal, viewport: &MapViewport, map: &Map, actors: Actors)
 where
     Actors: Iterator<Item = (&'a Renderable, &'a Position)>,
 {
-    render_map_in_view(view, map, term);
-    render_actors_in_view(view, map, term, actors);
+    render_map_in_view(view, map, viewport, term);
+    render_actors_in_view(view, map, viewport, term, actors);
 }
 
-fn render_map_in_view(view: &MapView, map: &Map, term: &mut Terminal) {
+fn render_map_in_view(view: &MapView, map: &Map, viewport: &MapViewport, term: &mut Terminal) {
     for (i, seen) in view.0.iter().enumerate() {
         if *seen {
             let p = map.0.index_to_pos(i);
             let tile = map.0[p];
             
-            // Convert to terminal position
-            term.put_tile(p, Tile::from(tile));
+            viewport.put_tile(term, p, Tile::from(tile));
         }
     }
 }
 
-fn render_actors_in_view<'a, Actors>(view: &MapView, map: &Map, term: &mut Terminal, actors: Actors)
+fn render_actors_in_view<'a, Actors>(view: &MapView, map: &Map, viewport: &MapViewport, term: &mut Terminal, actors: Actors)
 where
     Actors: Iterator<Item = (&'a Renderable, &'a Position)>,
 {
@@ -127,21 +131,21 @@ where
         let i = map.0.pos_to_index( pos.0 );
 
         if view.0[i] {
-            term.put_tile(pos.0, Tile::from(renderable));
+            viewport.put_tile(term, pos.0, Tile::from(renderable));
         }
     }
 }
 
-fn render_memory(memory: &MapMemory, map: &Map, term: &mut Terminal) {
+fn render_memory(memory: &MapMemory, map: &Map, viewport: &MapViewport, term: &mut Terminal) {
     for (i, remembered) in memory.0.iter().enumerate() {
         if *remembered {
-            let p = IVec2::from(term.to_xy(i));
+            let p = map.0.index_to_pos(i);
             let tile = map.0[p];
 
             let mut tile: Tile = tile.into();
             tile.fg_color = greyscale(tile.fg_color);
 
-            term.put_tile(p, tile);
+            viewport.put_tile(term, p, tile);
         }
     }
 }
@@ -153,52 +157,96 @@ fn greyscale(c: Color) -> Color {
     Color::rgb(grey, grey, grey)
 }
 
-fn render_everything<'a, Actors>(map: &Map, term: &mut Terminal, actors: Actors)
+fn render_everything<'a, Actors>(map: &Map, term: &mut Terminal, viewport: &MapViewport, actors: Actors)
 where
     Actors: Iterator<Item = (&'a Renderable, &'a Position)>,
 {
-    render_full_map(map, term);
-    render_all_entities(term, actors);
+    render_full_map(map, viewport, term);
+    render_all_entities(term, viewport, actors);
 }
-fn render_full_map(map: &Map, term: &mut Terminal) {
+fn render_full_map(map: &Map, viewport: &MapViewport, term: &mut Terminal) {
     for x in 0..map.0.width() as i32 {
         for y in 0..map.0.height() as i32 {
-            let tile: Tile = match map.0[ [x as u32, y as u32] ] {
-                MapTile::Wall => Tile {
-                    glyph: '#',
-                    fg_color: WALL_COLOR,
-                    bg_color: Color::BLACK,
-                },
-                MapTile::Floor => Tile {
-                    glyph: '.',
-                    fg_color: FLOOR_COLOR,
-                    bg_color: Color::BLACK,
-                },
-            };
-            term.put_tile([x as i32, y as i32], tile);
+            let tile = Tile::from(map.0[ [x as u32, y as u32] ]);
+            viewport.put_tile(term, IVec2::new(x, y), tile);
         }
     }
 }
 
-fn render_all_entities<'a, Entities>(term: &mut Terminal, entities: Entities)
+fn render_all_entities<'a, Entities>(term: &mut Terminal, viewport: &MapViewport, entities: Entities)
 where
     Entities: Iterator<Item = (&'a Renderable, &'a Position)>,
 {
     for (r, pos) in entities {
-        term.put_tile(pos.0, Tile::from(r));
+        viewport.put_tile(term, pos.0, Tile::from(r));
     }
 }
 
+/// Draw the line of fire and the target under the cursor over the map.
+#[allow(clippy::too_many_arguments)]
+fn render_targeting(
+    mode: Res<InputMode>,
+    targeting: Res<Targeting>,
+    actors: Res<MapActors>,
+    q_map: Query<&Map>,
+    q_player: Query<&Position, With<Player>>,
+    q_actors: Query<&Renderable>,
+    q_targets: Query<(&Name, &HitPoints, &MaxHitPoints)>,
+    viewport: Res<MapViewport>,
+    mut q_render_terminal: Query<&mut Terminal, With<GameTerminal>>,
+) {
+    if *mode != InputMode::Targeting {
+        return;
+    }
+
+    let (mut term, map, player) = match (q_render_terminal.get_single_mut(), q_map.get_single(), q_player.get_single()) {
+        (Ok(term), Ok(map), Ok(player)) => (term, map, player),
+        _ => return,
+    };
+
+    // Whatever is drawn on the tile, with a highlighted background
+    let highlight = |p: IVec2, color: Color| {
+        let mut tile = match actors.0[p].and_then(|e| q_actors.get(e).ok()) {
+            Some(renderable) => Tile::from(renderable),
+            None => Tile::from(map.0[p]),
+        };
+        tile.bg_color = color;
+        tile
+    };
+
+    let fire = line_of_fire(player.0, targeting.cursor, map, &actors);
+    for p in fire.path.iter() {
+        viewport.put_tile(&mut term, *p, highlight(*p, TARGET_PATH_COLOR));
+    }
+    viewport.put_tile(&mut term, targeting.cursor, highlight(targeting.cursor, TARGET_CURSOR_COLOR));
+
+    let target = match fire.hit.and_then(|e| q_targets.get(e).ok()) {
+        Some((name, hp, max)) => format!("{} ({}/{})", name.as_str(), hp.0, max.0),
+        None => "No target".to_string(),
+    };
+    let text = format!("{} - [Enter] Confirm [Tab] Next [Esc] Cancel", target);
+    let y = term.side_index(Side::Top) as i32;
+    term.put_string([2, y], text.as_str().fg(Color::YELLOW));
+}
+
 fn should_render(
     q_entities_changed: Query<(&Renderable, &Position), Changed<Position>>,
     q_map_changed: Query<&Map, Changed<Map>>,
     mut evt_killed: EventReader<ActorKilledEvent>,
+    removed: RemovedComponents<Position>,
+    mode: Res<InputMode>,
+    targeting: Res<Targeting>,
+    viewport: Res<MapViewport>,
 ) -> ShouldRun {
-    let entities_changed = q_entities_changed.iter().next().is_some();
+    // Items lose their position when they're picked up
+    let entities_changed = q_entities_changed.iter().next().is_some() || removed.iter().next().is_some();
     let map_changed = q_map_changed.iter().next().is_some();
     let killed = evt_killed.iter().next().is_some();
 
-    if map_changed || entities_changed || killed {
+    // Redraw to move or clear the targeting overlay
+    let targeting_changed = mode.is_changed() || targeting.is_changed();
+
+    if map_changed || entities_changed || killed || targeting_changed || viewport.is_changed() {
         return ShouldRun::Yes;
     }
 
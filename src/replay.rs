@@ -0,0 +1,342 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use bevy::{app::AppExit, prelude::*};
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    action::{Action, ActionEvent},
+    combat::HitPoints,
+    config::{self, MapGenSettings},
+    player::Player,
+    rng::RunSeed,
+    save::{LoadedGame, SaveGame},
+    turn_system::TakingATurn,
+};
+
+/// Label for the system feeding replayed actions to the player. Occurs in [CoreStage::PreUpdate].
+pub const REPLAY_INPUT_SYSTEM_LABEL: &str = "replay_input";
+
+/// Bumped whenever the replay format or anything affecting how a replay plays out changes.
+pub const REPLAY_VERSION: u32 = 11;
+
+/// Where the current run is recorded unless `--record <path>` is given.
+pub const DEFAULT_RECORDING_PATH: &str = "replays/latest.ron";
+
+/// Seconds between replayed actions during normal playback.
+const PLAYBACK_INTERVAL: f32 = 0.15;
+
+/// Records every player action to a replay file, or plays one back.
+///
+/// Run with `--replay <path>` to play back a recording, optionally with
+/// `--headless` to run it without a window as fast as possible.
+///
+/// Playback controls: `Space` pauses, `S` steps a single turn while paused,
+/// `F` toggles fast-forward.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let replay = config::arg_value("--replay").and_then(|path| {
+            match Replay::load(&path) {
+                Ok(replay) => Some(replay),
+                Err(e) => {
+                    eprintln!("{}. Starting a new game instead.", e);
+                    None
+                }
+            }
+        });
+
+        match replay {
+            Some(replay) => {
+                println!("Playing back {} actions from seed {}", replay.actions.len(), replay.seed);
+                if let Some(start) = replay.start {
+                    app.insert_resource(LoadedGame(start));
+                }
+                app.insert_resource(RunSeed(replay.seed))
+                .insert_resource(replay.settings)
+                .insert_resource(ReplayPlayback::new(replay.actions, config::has_arg("--headless")))
+                .add_system_to_stage(CoreStage::PreUpdate, replay_input.label(REPLAY_INPUT_SYSTEM_LABEL))
+                .add_system(replay_controls);
+            },
+            None => {
+                app.add_startup_system(start_recording)
+                .add_system(record_actions)
+                .add_system_to_stage(CoreStage::Last, save_recording);
+            },
+        }
+    }
+}
+
+/// A recorded game session. The seed and map settings together with the
+/// player's actions are enough to reproduce the whole session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub settings: MapGenSettings,
+    /// The saved game the session was resumed from, if any.
+    #[serde(default)]
+    pub start: Option<SaveGame>,
+    pub actions: Vec<Action>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, String> {
+        let path = path.as_ref();
+        let file_string = match fs::read_to_string(path) {
+            Ok(file_string) => file_string,
+            Err(e) => return Err(format!("Error reading replay {}: {}", path.display(), e)),
+        };
+
+        let replay: Replay = match ron::from_str(&file_string) {
+            Ok(replay) => replay,
+            Err(e) => return Err(format!("Error parsing replay {}: {}", path.display(), e)),
+        };
+
+        if replay.version != REPLAY_VERSION {
+            return Err(format!(
+                "Replay {} was recorded with version {}, expected version {}",
+                path.display(),
+                replay.version,
+                REPLAY_VERSION
+            ));
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(format!("Error creating {}: {}", dir.display(), e));
+            }
+        }
+
+        let contents = match to_string_pretty(self, PrettyConfig::default()) {
+            Ok(contents) => contents,
+            Err(e) => return Err(format!("Error serializing replay: {}", e)),
+        };
+
+        match fs::write(path, contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Error writing replay {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Records the player's actions for the current run. The recording is
+/// written when the game exits.
+pub struct ReplayRecorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+/// Feeds a recorded session back through the player's actions.
+pub struct ReplayPlayback {
+    actions: Vec<Action>,
+    next: usize,
+    paused: bool,
+    step: bool,
+    fast_forward: bool,
+    headless: bool,
+    finished: bool,
+    timer: Timer,
+}
+
+impl ReplayPlayback {
+    pub fn new(actions: Vec<Action>, headless: bool) -> Self {
+        Self {
+            actions,
+            next: 0,
+            paused: false,
+            step: false,
+            fast_forward: false,
+            headless,
+            finished: false,
+            timer: Timer::from_seconds(PLAYBACK_INTERVAL, true),
+        }
+    }
+
+    /// Whether the next action should be played this frame.
+    fn ready(&mut self) -> bool {
+        if self.headless || (self.fast_forward && !self.paused) {
+            return true;
+        }
+        if self.paused {
+            return std::mem::take(&mut self.step);
+        }
+        self.timer.just_finished()
+    }
+}
+
+fn start_recording(
+    mut commands: Commands,
+    seed: Res<RunSeed>,
+    settings: Res<MapGenSettings>,
+    loaded: Option<Res<LoadedGame>>,
+) {
+    let path = config::arg_value("--record").unwrap_or_else(|| DEFAULT_RECORDING_PATH.to_string());
+    commands.insert_resource(ReplayRecorder {
+        path: PathBuf::from(path),
+        replay: Replay {
+            version: REPLAY_VERSION,
+            seed: seed.0,
+            settings: settings.clone(),
+            start: loaded.map(|loaded| loaded.0.clone()),
+            actions: Vec::new(),
+        },
+    });
+}
+
+fn record_actions(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    q_player: Query<&Player>,
+    mut evt_action: EventReader<ActionEvent>,
+) {
+    let mut recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+
+    let actions = evt_action.iter().filter(|ev| q_player.get(ev.actor).is_ok());
+    recorder.replay.actions.extend(actions.map(|ev| ev.action));
+}
+
+fn save_recording(
+    recorder: Option<Res<ReplayRecorder>>,
+    mut evt_exit: EventReader<AppExit>,
+) {
+    if evt_exit.iter().next().is_none() {
+        return;
+    }
+
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.replay.save(&recorder.path) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn replay_input(
+    q_player: Query<Option<&HitPoints>, With<Player>>,
+    q_player_turn: Query<Entity, (With<Player>, With<TakingATurn>)>,
+    mut playback: ResMut<ReplayPlayback>,
+    time: Res<Time>,
+    mut evt_action: EventWriter<ActionEvent>,
+    mut evt_exit: EventWriter<AppExit>,
+) {
+    if playback.finished {
+        return;
+    }
+
+    let player_alive = !q_player.is_empty();
+    if !player_alive || playback.next >= playback.actions.len() {
+        playback.finished = true;
+        println!(
+            "Replay finished: played {} of {} actions.",
+            playback.next,
+            playback.actions.len()
+        );
+        match q_player.get_single() {
+            Ok(Some(hp)) => println!("Player HP: {}", hp.0),
+            _ => println!("The player is dead."),
+        }
+        if playback.headless {
+            evt_exit.send(AppExit);
+        }
+        return;
+    }
+
+    let player = match q_player_turn.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    playback.timer.tick(time.delta());
+    if !playback.ready() {
+        return;
+    }
+
+    let action = playback.actions[playback.next];
+    playback.next += 1;
+    evt_action.send(ActionEvent { actor: player, action });
+}
+
+fn replay_controls(
+    input: Res<Input<KeyCode>>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    if input.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+        println!("Replay {}", if playback.paused { "paused" } else { "resumed" });
+    }
+    if input.just_pressed(KeyCode::S) && playback.paused {
+        playback.step = true;
+    }
+    if input.just_pressed(KeyCode::F) {
+        playback.fast_forward = !playback.fast_forward;
+        println!("Replay fast-forward {}", if playback.fast_forward { "on" } else { "off" });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::send;
+
+    use super::*;
+
+    #[test]
+    fn recorded_actions_load_back() {
+        let path = std::env::temp_dir().join("bevy_roguelike_recorded_actions_load_back.ron");
+        let _ = std::fs::remove_file(&path);
+        let mut app = App::new();
+        app.add_event::<ActionEvent>()
+        .add_event::<AppExit>()
+        .insert_resource(ReplayRecorder {
+            path: path.clone(),
+            replay: Replay {
+                version: REPLAY_VERSION,
+                seed: 7,
+                settings: MapGenSettings::default(),
+                start: None,
+                actions: Vec::new(),
+            },
+        })
+        .add_system(record_actions)
+        .add_system_to_stage(CoreStage::Last, save_recording);
+        let player = app.world.spawn().insert(Player).id();
+        let monster = app.world.spawn().id();
+
+        send(&mut app, ActionEvent { actor: player, action: Action::Move([1, 0]) });
+        send(&mut app, ActionEvent { actor: monster, action: Action::Wait });
+        send(&mut app, ActionEvent { actor: player, action: Action::PickUp });
+        // Nothing is written until the game exits
+        assert!(Replay::load(&path).is_err());
+
+        send(&mut app, AppExit);
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(7, replay.seed);
+        assert_eq!(vec![Action::Move([1, 0]), Action::PickUp], replay.actions);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let path = std::env::temp_dir().join("bevy_roguelike_other_versions_are_rejected.ron");
+        let replay = Replay {
+            version: REPLAY_VERSION + 1,
+            seed: 0,
+            settings: MapGenSettings::default(),
+            start: None,
+            actions: vec![Action::Wait],
+        };
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_err());
+    }
+}
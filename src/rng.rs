@@ -1,24 +1,99 @@
+use bevy::prelude::*;
 use bracket_random::prelude::{RandomNumberGenerator, DiceType};
 
+use crate::config::{self, MapGenSettings};
 
-pub struct DiceRng {
-    rng: RandomNumberGenerator,
+/// Label for the system that seeds the run. Occurs in [StartupStage::PreStartup].
+pub const RNG_SETUP_LABEL: &str = "rng_setup";
+
+pub struct RngPlugin;
+
+impl Plugin for RngPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PreStartup, setup.label(RNG_SETUP_LABEL));
+    }
 }
 
-impl Default for DiceRng {
-    fn default() -> Self {
-        Self { 
-            rng: RandomNumberGenerator::new(),
+/// The seed for the current run. Map generation, monster placement and every
+/// dice roll are derived from it, so the same seed and the same player input
+/// will always play out the same way.
+///
+/// Can be set from the command line with `--seed <number>`, otherwise it's read
+/// from the map settings.
+#[derive(Debug, Clone, Copy)]
+pub struct RunSeed(pub u64);
+
+fn setup(
+    mut commands: Commands,
+    settings: Res<MapGenSettings>,
+    seed: Option<Res<RunSeed>>,
+) {
+    let seed = match seed {
+        Some(seed) => *seed,
+        None => RunSeed(seed_from_args().unwrap_or(settings.seed)),
+    };
+
+    info!("Run seed: {}", seed.0);
+
+    commands.insert_resource(DiceRng::seeded(seed.0));
+    commands.insert_resource(seed);
+}
+
+fn seed_from_args() -> Option<u64> {
+    let arg = config::arg_value("--seed")?;
+    match arg.parse() {
+        Ok(seed) => Some(seed),
+        Err(e) => {
+            eprintln!("Invalid seed '{}': {}", arg, e);
+            None
         }
     }
 }
 
+/// Shared dice roller for the run, seeded from the [RunSeed].
+pub struct DiceRng {
+    rng: RandomNumberGenerator,
+    rolls: u64,
+}
+
 impl DiceRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            rng: RandomNumberGenerator::seeded(seed),
+            rolls: 0,
+        }
+    }
+
+    /// Continue a run that has already made `rolls` rolls, ie: from a saved game.
+    ///
+    /// The generator's internal state can't be saved, so it's reseeded from the
+    /// run seed and roll count instead. Resuming the same save will always
+    /// produce the same rolls.
+    pub fn resumed(seed: u64, rolls: u64) -> Self {
+        let mixed = seed ^ rolls.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        Self {
+            rng: RandomNumberGenerator::seeded(mixed),
+            rolls,
+        }
+    }
+
+    /// How many rolls have been made this run.
+    pub fn rolls(&self) -> u64 {
+        self.rolls
+    }
+
     pub fn roll(&mut self, dice: DiceType) -> i32 {
-        self.rng.roll(dice) 
+        self.rolls += 1;
+        self.rng.roll(dice)
+    }
+
+    /// A random number from `min` up to but not including `max`.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        self.rolls += 1;
+        self.rng.range(min, max)
     }
 
     // pub fn roll_dice(&mut self, count: i32, faces: i32) -> i32 {
     //     self.rng.roll_dice(count, faces)
     // }
-}
\ No newline at end of file
+}
@@ -0,0 +1,650 @@
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+use bevy::{app::AppExit, ecs::system::EntityCommands, prelude::*};
+use ron::ser::{to_string_pretty, PrettyConfig};
+use sark_grids::Grid;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ai::MonsterAi,
+    bundle::MovingEntityBundle,
+    combat::{AttackDice, CombatantBundle, Defense, HitPoints, MaxHitPoints, Strength},
+    config::{self, MapGenSettings},
+    dungeon::{Dungeon, LevelData},
+    equipment::Equipment,
+    inventory::{Inventory, Item, ItemTemplates, INVENTORY_INPUT_SYSTEM_LABEL},
+    map::{Map, MapTile, MAP_GEN_SETUP_LABEL},
+    monster::{Monster, MonsterBundle},
+    movement::Position,
+    player::{InputMode, Player, PLAYER_INVENTORY_CAPACITY},
+    render::Renderable,
+    replay::ReplayPlayback,
+    rng::{DiceRng, RunSeed},
+    targeting::TARGETING_INPUT_SYSTEM_LABEL,
+    turn_system::{Energy, NextTurnIn, Speed, TakingATurn, TurnOrder, TurnQueue},
+    ui::PrintLog,
+    visibility::{MapMemory, ViewRange},
+};
+
+/// Bumped whenever the save format changes. Saves from other versions are ignored.
+pub const SAVE_VERSION: u32 = 7;
+
+pub const SAVE_FILE_PATH: &str = "saves/savegame.ron";
+
+/// Label for the system restoring a loaded game. Occurs during startup, after the map is set up.
+pub const RESTORE_GAME_SYSTEM_LABEL: &str = "restore_game";
+/// Label for the system saving and quitting on `Escape`. Occurs in [CoreStage::PreUpdate].
+pub const QUIT_INPUT_SYSTEM_LABEL: &str = "quit_input";
+
+/// Saves the game when quitting and resumes it on the next launch.
+///
+/// Press `Escape` to save and quit. Run with `--new` to ignore an existing save
+/// and start a new game. Replays never read or write the save file.
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(restore_game
+            .label(RESTORE_GAME_SYSTEM_LABEL)
+            .after(MAP_GEN_SETUP_LABEL)
+        );
+
+        if app.world.contains_resource::<ReplayPlayback>() {
+            return;
+        }
+
+        if !config::has_arg("--new") {
+            match SaveGame::load(SAVE_FILE_PATH) {
+                Ok(Some(save)) => {
+                    println!("Resuming saved game");
+                    app.insert_resource(RunSeed(save.seed))
+                    .insert_resource(save.settings.clone())
+                    .insert_resource(LoadedGame(save));
+                },
+                Ok(None) => {},
+                Err(e) => eprintln!("{}. Starting a new game instead.", e),
+            }
+        }
+
+        app.add_system_to_stage(CoreStage::PreUpdate, quit_input
+            .label(QUIT_INPUT_SYSTEM_LABEL)
+            // Escape closes the inventory or cancels targeting before it quits the game
+            .before(INVENTORY_INPUT_SYSTEM_LABEL)
+            .before(TARGETING_INPUT_SYSTEM_LABEL)
+        )
+        .add_system_to_stage(CoreStage::Last, save_on_exit);
+    }
+}
+
+/// A game loaded from a save, restored during startup instead of generating a new map.
+pub struct LoadedGame(pub SaveGame);
+
+/// Everything needed to resume a game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub seed: u64,
+    /// How many dice had been rolled, see [DiceRng::resumed].
+    pub rolls: u64,
+    pub settings: MapGenSettings,
+    pub map: MapData,
+    pub player: ActorData,
+    pub memory: MapMemory,
+    pub monsters: Vec<MonsterData>,
+    /// Items lying on the map.
+    pub items: Vec<ItemData>,
+    /// Names of the items the player is carrying.
+    pub inventory: Vec<String>,
+    /// Indices into `inventory` of the items the player has equipped.
+    pub equipped: Vec<usize>,
+    pub log: PrintLog,
+    pub depth: u32,
+    /// Levels other than the current one.
+    pub levels: BTreeMap<u32, LevelData>,
+}
+
+impl SaveGame {
+    /// Load a save file. Returns `None` if there is no save.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<SaveGame>, String> {
+        let path = path.as_ref();
+        let file_string = match fs::read_to_string(path) {
+            Ok(file_string) => file_string,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Error reading save {}: {}", path.display(), e)),
+        };
+
+        // Check the version first, an old save probably won't parse
+        #[derive(Deserialize)]
+        #[serde(rename = "SaveGame")]
+        struct Version {
+            version: u32,
+        }
+        match ron::from_str::<Version>(&file_string) {
+            Ok(v) if v.version == SAVE_VERSION => {},
+            Ok(v) => return Err(format!(
+                "Save {} is version {}, expected version {}",
+                path.display(),
+                v.version,
+                SAVE_VERSION
+            )),
+            Err(e) => return Err(format!("Error parsing save {}: {}", path.display(), e)),
+        }
+
+        let save: SaveGame = match ron::from_str(&file_string) {
+            Ok(save) => save,
+            Err(e) => return Err(format!("Error parsing save {}: {}", path.display(), e)),
+        };
+
+        save.map.validate().map_err(|e| format!("Invalid save {}: {}", path.display(), e))?;
+        if save.memory.0.len() != save.map.tiles.len() {
+            return Err(format!("Invalid save {}: map memory doesn't match the map size", path.display()));
+        }
+
+        Ok(Some(save))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(format!("Error creating {}: {}", dir.display(), e));
+            }
+        }
+
+        let contents = match to_string_pretty(self, PrettyConfig::default()) {
+            Ok(contents) => contents,
+            Err(e) => return Err(format!("Error serializing save: {}", e)),
+        };
+
+        match fs::write(path, contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Error writing save {}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapData {
+    pub size: [u32;2],
+    pub tiles: Vec<MapTile>,
+}
+
+impl MapData {
+    fn validate(&self) -> Result<(), String> {
+        let len = self.size[0] as usize * self.size[1] as usize;
+        if len != self.tiles.len() {
+            return Err(format!(
+                "map is {}x{} but has {} tiles",
+                self.size[0],
+                self.size[1],
+                self.tiles.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl From<&Map> for MapData {
+    fn from(map: &Map) -> Self {
+        MapData {
+            size: map.0.size().into(),
+            tiles: map.0.iter().copied().collect(),
+        }
+    }
+}
+
+impl From<&MapData> for Map {
+    fn from(data: &MapData) -> Self {
+        let mut grid = Grid::default(data.size);
+        for (i, tile) in data.tiles.iter().enumerate() {
+            grid[i] = *tile;
+        }
+        Map(grid)
+    }
+}
+
+/// The saved state of a player or monster.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorData {
+    pub name: String,
+    pub position: Position,
+    pub renderable: Renderable,
+    pub hp: HitPoints,
+    pub max_hp: MaxHitPoints,
+    pub defense: Defense,
+    pub strength: Strength,
+    pub attack_dice: AttackDice,
+    pub energy: Energy,
+    pub speed: Speed,
+    pub view_range: ViewRange,
+    pub taking_turn: bool,
+    /// Missing if the actor hadn't been scheduled yet.
+    pub turn_order: Option<TurnOrder>,
+    /// Ticks left until the actor's next turn. Missing if it's taking a turn
+    /// or hadn't been scheduled yet.
+    pub next_turn_in: Option<u64>,
+}
+
+pub type ActorComponents<'a> = (
+    &'a Name,
+    &'a Position,
+    &'a Renderable,
+    &'a HitPoints,
+    &'a MaxHitPoints,
+    &'a Defense,
+    &'a Strength,
+    &'a AttackDice,
+    &'a Energy,
+    &'a Speed,
+    &'a ViewRange,
+    Option<&'a TakingATurn>,
+    Option<&'a TurnOrder>,
+);
+
+impl ActorData {
+    /// `next_turn_in` comes from the [TurnQueue], see [TurnQueue::ticks_until].
+    pub fn from_components(
+        (name, position, renderable, hp, max_hp, defense, strength, attack_dice, energy, speed, view_range, taking_turn, turn_order): ActorComponents,
+        next_turn_in: Option<u64>,
+    ) -> Self {
+        ActorData {
+            name: name.to_string(),
+            position: *position,
+            renderable: renderable.clone(),
+            hp: hp.clone(),
+            max_hp: max_hp.clone(),
+            defense: defense.clone(),
+            strength: strength.clone(),
+            attack_dice: attack_dice.clone(),
+            energy: energy.clone(),
+            speed: speed.clone(),
+            view_range: view_range.clone(),
+            taking_turn: taking_turn.is_some(),
+            turn_order: turn_order.copied(),
+            next_turn_in,
+        }
+    }
+
+    /// Overwrite an existing entity's components with the saved ones.
+    fn insert_into(&self, entity: &mut EntityCommands) {
+        entity
+            .insert(Name::new(self.name.clone()))
+            .insert(self.position)
+            .insert(self.renderable.clone())
+            .insert(self.hp.clone())
+            .insert(self.max_hp.clone())
+            .insert(self.defense.clone())
+            .insert(self.strength.clone())
+            .insert(self.attack_dice.clone())
+            .insert(self.energy.clone())
+            .insert(self.speed.clone())
+            .insert(self.view_range.clone());
+        self.insert_turn_state(entity);
+    }
+
+    /// Put the actor back where it was in the turn queue.
+    pub fn insert_turn_state(&self, entity: &mut EntityCommands) {
+        if self.taking_turn {
+            entity.insert(TakingATurn);
+        }
+        if let Some(order) = self.turn_order {
+            entity.insert(order);
+        }
+        if let Some(ticks) = self.next_turn_in {
+            entity.insert(NextTurnIn(ticks));
+        }
+    }
+}
+
+/// The saved state of a monster.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonsterData {
+    pub actor: ActorData,
+    pub ai: MonsterAi,
+}
+
+impl MonsterData {
+    pub fn from_components(actor: ActorComponents, next_turn_in: Option<u64>, ai: &MonsterAi) -> Self {
+        MonsterData {
+            actor: ActorData::from_components(actor, next_turn_in),
+            ai: ai.clone(),
+        }
+    }
+
+    pub fn monster_bundle(&self) -> MonsterBundle {
+        let actor = &self.actor;
+        MonsterBundle {
+            movable: MovingEntityBundle {
+                renderable: actor.renderable.clone(),
+                position: actor.position,
+                movement: Default::default(),
+                energy: actor.energy.clone(),
+                speed: actor.speed.clone(),
+                actor: Default::default(),
+            },
+            combatant_bundle: CombatantBundle {
+                hp: actor.hp.clone(),
+                max_hp: actor.max_hp.clone(),
+                defense: actor.defense.clone(),
+                strength: actor.strength.clone(),
+                attack_dice: actor.attack_dice.clone(),
+            },
+            monster: Default::default(),
+            name: Name::new(actor.name.clone()),
+            blocker: Default::default(),
+            vision: Default::default(),
+            view_range: actor.view_range.clone(),
+            ai: self.ai.clone(),
+        }
+    }
+}
+
+/// The saved state of an item lying on the map.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemData {
+    pub name: String,
+    pub position: Position,
+}
+
+impl ItemData {
+    pub fn new(name: &Name, position: &Position) -> Self {
+        ItemData {
+            name: name.to_string(),
+            position: *position,
+        }
+    }
+
+    /// Spawn the item back onto the map.
+    pub fn spawn(&self, commands: &mut Commands, items: &ItemTemplates) -> Option<Entity> {
+        let entity = items.spawn(commands, &self.name)?;
+        commands.entity(entity).insert(self.position);
+        Some(entity)
+    }
+}
+
+fn restore_game(
+    mut commands: Commands,
+    loaded: Option<Res<LoadedGame>>,
+    q_player: Query<Entity, With<Player>>,
+    mut log: ResMut<PrintLog>,
+    mut dungeon: ResMut<Dungeon>,
+    items: Res<ItemTemplates>,
+) {
+    let save = match &loaded {
+        Some(loaded) => &loaded.0,
+        None => return,
+    };
+
+    commands.spawn().insert(Map::from(&save.map));
+
+    if let Ok(player) = q_player.get_single() {
+        let mut inventory = Inventory::new(PLAYER_INVENTORY_CAPACITY);
+        let mut equipment = Equipment::default();
+        for (i, name) in save.inventory.iter().enumerate() {
+            if let Some(item) = items.spawn(&mut commands, name) {
+                inventory.items.push(item);
+
+                let slot = items.get(name).and_then(|t| t.equippable.as_ref()).map(|e| e.slot);
+                if let (true, Some(slot)) = (save.equipped.contains(&i), slot) {
+                    equipment.slots.insert(slot, item);
+                }
+            }
+        }
+
+        let mut player = commands.entity(player);
+        save.player.insert_into(&mut player);
+        player.insert(save.memory.clone())
+            .insert(inventory)
+            .insert(equipment);
+    }
+
+    for item in save.items.iter() {
+        item.spawn(&mut commands, &items);
+    }
+
+    for monster in save.monsters.iter() {
+        let mut entity = commands.spawn_bundle(monster.monster_bundle());
+        monster.actor.insert_turn_state(&mut entity);
+    }
+
+    *log = save.log.clone();
+    dungeon.depth = save.depth;
+    dungeon.levels = save.levels.clone();
+    commands.insert_resource(DiceRng::resumed(save.seed, save.rolls));
+}
+
+fn quit_input(
+    input: Res<Input<KeyCode>>,
+    mode: Res<InputMode>,
+    mut evt_exit: EventWriter<AppExit>,
+) {
+    if *mode == InputMode::Game && input.just_pressed(KeyCode::Escape) {
+        evt_exit.send(AppExit);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_on_exit(
+    mut evt_exit: EventReader<AppExit>,
+    q_player: Query<(Entity, ActorComponents, &MapMemory, &Inventory, &Equipment), With<Player>>,
+    q_monsters: Query<(Entity, ActorComponents, &MonsterAi), With<Monster>>,
+    q_items: Query<(&Name, &Position), With<Item>>,
+    q_names: Query<&Name, With<Item>>,
+    q_map: Query<&Map>,
+    log: Res<PrintLog>,
+    rng: Res<DiceRng>,
+    seed: Res<RunSeed>,
+    settings: Res<MapGenSettings>,
+    dungeon: Res<Dungeon>,
+    queue: Res<TurnQueue>,
+) {
+    if evt_exit.iter().next().is_none() {
+        return;
+    }
+
+    let (entity, player, memory, inventory, equipment) = match q_player.get_single() {
+        Ok(player) => player,
+        Err(_) => {
+            // Dead players don't get to resume
+            if let Err(e) = fs::remove_file(SAVE_FILE_PATH) {
+                if e.kind() != ErrorKind::NotFound {
+                    eprintln!("Error removing save {}: {}", SAVE_FILE_PATH, e);
+                }
+            }
+            return;
+        }
+    };
+
+    let map = match q_map.get_single() {
+        Ok(map) => map,
+        Err(_) => return,
+    };
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        seed: seed.0,
+        rolls: rng.rolls(),
+        settings: settings.clone(),
+        map: MapData::from(map),
+        player: ActorData::from_components(player, queue.ticks_until(entity)),
+        memory: memory.clone(),
+        monsters: q_monsters.iter()
+            .map(|(entity, actor, ai)| MonsterData::from_components(actor, queue.ticks_until(entity), ai))
+            .collect(),
+        items: q_items.iter().map(|(name, pos)| ItemData::new(name, pos)).collect(),
+        inventory: inventory.items.iter()
+            .filter_map(|item| q_names.get(*item).ok())
+            .map(|name| name.to_string())
+            .collect(),
+        equipped: inventory.items.iter()
+            .enumerate()
+            .filter(|(_, item)| equipment.slot_of(**item).is_some())
+            .map(|(i, _)| i)
+            .collect(),
+        log: log.clone(),
+        depth: dungeon.depth,
+        levels: dungeon.levels.clone(),
+    };
+
+    match save.save(SAVE_FILE_PATH) {
+        Ok(_) => println!("Game saved to {}", SAVE_FILE_PATH),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ai::{AiSettings, AiState},
+        turn_system::{Actor, TurnSystemPlugin},
+    };
+
+    use super::*;
+
+    /// The components saved for an actor.
+    fn actor(name: &str, pos: IVec2, hp: i32, order: u64) -> impl Bundle {
+        (
+            Name::new(name.to_string()),
+            Position(pos),
+            Renderable { fg_color: Color::WHITE, bg_color: Color::BLACK, glyph: '@' },
+            HitPoints(hp),
+            MaxHitPoints(10),
+            Defense(1),
+            Strength(2),
+            AttackDice::parse("1d4").unwrap(),
+            Energy(40),
+            Speed(10),
+            ViewRange(5),
+            TurnOrder(order),
+        )
+    }
+
+    /// Save the player and monsters in the world to a file and load it back.
+    fn save_and_load(world: &mut World, queue: &TurnQueue, map: &Map, file_name: &str) -> SaveGame {
+        let mut q_player = world.query_filtered::<(Entity, ActorComponents), With<Player>>();
+        let (entity, player) = q_player.iter(world).next().unwrap();
+        let player = ActorData::from_components(player, queue.ticks_until(entity));
+        let mut q_monsters = world.query_filtered::<(Entity, ActorComponents, &MonsterAi), With<Monster>>();
+        let monsters = q_monsters.iter(world)
+            .map(|(entity, actor, ai)| MonsterData::from_components(actor, queue.ticks_until(entity), ai))
+            .collect();
+
+        let mut rng = DiceRng::seeded(9);
+        for _ in 0..3 {
+            rng.range(0, 10);
+        }
+        let save = SaveGame {
+            version: SAVE_VERSION,
+            seed: 9,
+            rolls: rng.rolls(),
+            settings: MapGenSettings::default(),
+            map: MapData::from(map),
+            player,
+            memory: MapMemory(vec![true; map.0.len()]),
+            monsters,
+            items: Vec::new(),
+            inventory: Vec::new(),
+            equipped: Vec::new(),
+            log: PrintLog::default(),
+            depth: 2,
+            levels: BTreeMap::new(),
+        };
+
+        let path = std::env::temp_dir().join(file_name);
+        save.save(&path).unwrap();
+        let loaded = SaveGame::load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    fn corridor() -> Map {
+        let mut map = Map(Grid::default([5, 3]));
+        for x in 1..4 {
+            map.0[[x, 1]] = MapTile::Floor;
+        }
+        map.0[[3, 1]] = MapTile::DownStairs;
+        map
+    }
+
+    fn rat_ai() -> MonsterAi {
+        MonsterAi {
+            settings: AiSettings::default(),
+            state: AiState::Chase { last_seen: IVec2::new(1, 1) },
+        }
+    }
+
+    #[test]
+    fn saved_games_restore() {
+        let map = corridor();
+        let mut world = World::new();
+        world.spawn().insert_bundle(actor("Player", IVec2::new(1, 1), 7, 0)).insert(Player);
+        world.spawn().insert_bundle(actor("Rat", IVec2::new(2, 1), 3, 1)).insert_bundle((Monster, rat_ai(), TakingATurn));
+        let loaded = save_and_load(&mut world, &TurnQueue::default(), &map, "bevy_roguelike_saved_games_restore.ron");
+
+        let mut app = App::new();
+        app.init_resource::<PrintLog>()
+        .init_resource::<Dungeon>()
+        .init_resource::<ItemTemplates>()
+        .insert_resource(LoadedGame(loaded))
+        .add_system(restore_game);
+        let player = app.world.spawn().insert(Player).id();
+        app.update();
+
+        let mut q_map = app.world.query::<&Map>();
+        assert!(map.0.iter().eq(q_map.iter(&app.world).next().unwrap().0.iter()));
+        assert_eq!(2, app.world.resource::<Dungeon>().depth);
+
+        assert_eq!(IVec2::new(1, 1), app.world.get::<Position>(player).unwrap().0);
+        assert_eq!(7, app.world.get::<HitPoints>(player).unwrap().0);
+        assert_eq!(40, app.world.get::<Energy>(player).unwrap().0);
+        assert_eq!(Some(&TurnOrder(0)), app.world.get::<TurnOrder>(player));
+        assert!(app.world.get::<TakingATurn>(player).is_none());
+
+        let mut q_monsters = app.world.query_filtered::<(&Name, &Position, &HitPoints, &MonsterAi, &TurnOrder), (With<Monster>, With<TakingATurn>)>();
+        let monsters: Vec<_> = q_monsters.iter(&app.world).collect();
+        assert_eq!(1, monsters.len());
+        let (name, pos, hp, ai, order) = monsters[0];
+        assert_eq!("Rat", name.as_str());
+        assert_eq!(IVec2::new(2, 1), pos.0);
+        assert_eq!(3, hp.0);
+        assert_eq!(AiState::Chase { last_seen: IVec2::new(1, 1) }, ai.state);
+        assert_eq!(TurnOrder(1), *order);
+
+        // Rolls carry on from where the save left off
+        let mut restored = app.world.resource_mut::<DiceRng>();
+        assert_eq!(3, restored.rolls());
+        assert_eq!(DiceRng::resumed(9, 3).range(0, 1000), restored.range(0, 1000));
+    }
+
+    #[test]
+    fn turn_order_survives_loading() {
+        let map = corridor();
+        let mut world = World::new();
+        let player = world.spawn().insert_bundle(actor("Player", IVec2::new(1, 1), 7, 0)).insert(Player).id();
+        let rat = world.spawn().insert_bundle(actor("Rat", IVec2::new(2, 1), 3, 1)).insert_bundle((Monster, rat_ai())).id();
+
+        // Both have the same energy and speed, but the rat is closer to its next turn
+        let mut queue = TurnQueue::default();
+        queue.schedule_in(player, TurnOrder(0), 5);
+        queue.schedule_in(rat, TurnOrder(1), 2);
+        let loaded = save_and_load(&mut world, &queue, &map, "bevy_roguelike_turn_order_survives_loading.ron");
+        assert_eq!(Some(5), loaded.player.next_turn_in);
+        assert_eq!(Some(2), loaded.monsters[0].actor.next_turn_in);
+
+        let mut app = App::new();
+        app.init_resource::<PrintLog>()
+        .init_resource::<Dungeon>()
+        .init_resource::<ItemTemplates>()
+        .init_resource::<InputMode>()
+        .insert_resource(LoadedGame(loaded))
+        .add_plugin(TurnSystemPlugin)
+        .add_startup_system(restore_game);
+        let player = app.world.spawn().insert_bundle((Player, Actor)).id();
+        app.update();
+
+        let mut q_acting = app.world.query_filtered::<&Name, With<TakingATurn>>();
+        let acting: Vec<_> = q_acting.iter(&app.world).map(|name| name.to_string()).collect();
+        assert_eq!(vec!["Rat".to_string()], acting);
+        assert_eq!(Some(3), app.world.resource::<TurnQueue>().ticks_until(player));
+    }
+}
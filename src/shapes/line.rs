@@ -0,0 +1,107 @@
+use bevy::math::IVec2;
+
+/// A line between two points on a grid, using Bresenham's algorithm.
+///
+/// Points on the line can be iterated over, starting at `start` and including `end`.
+pub struct Line {
+    pub start: IVec2,
+    pub end: IVec2,
+}
+
+impl Line {
+    pub fn new(start: impl Into<IVec2>, end: impl Into<IVec2>) -> Self {
+        Line {
+            start: start.into(),
+            end: end.into(),
+        }
+    }
+
+    /// An iterator over all grid positions on the line.
+    pub fn iter(&self) -> LineIterator {
+        LineIterator::from_line(self)
+    }
+}
+
+pub struct LineIterator {
+    current: IVec2,
+    end: IVec2,
+    delta: IVec2,
+    step: IVec2,
+    error: i32,
+    done: bool,
+}
+
+impl LineIterator {
+    pub fn from_line(line: &Line) -> Self {
+        let d = line.end - line.start;
+        LineIterator {
+            current: line.start,
+            end: line.end,
+            delta: IVec2::new(d.x.abs(), -d.y.abs()),
+            step: d.signum(),
+            error: d.x.abs() - d.y.abs(),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for LineIterator {
+    type Item = IVec2;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let p = self.current;
+        if p == self.end {
+            self.done = true;
+            return Some(p);
+        }
+
+        let e2 = self.error * 2;
+        if e2 >= self.delta.y {
+            self.error += self.delta.y;
+            self.current.x += self.step.x;
+        }
+        if e2 <= self.delta.x {
+            self.error += self.delta.x;
+            self.current.y += self.step.y;
+        }
+
+        Some(p)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::IVec2;
+
+    use super::Line;
+
+    #[test]
+    fn endpoints() {
+        let points: Vec<_> = Line::new([1, 2], [7, -3]).iter().collect();
+
+        assert_eq!(IVec2::new(1, 2), points[0]);
+        assert_eq!(IVec2::new(7, -3), points[points.len() - 1]);
+        // One point per step along the longest axis
+        assert_eq!(7, points.len());
+    }
+
+    #[test]
+    fn single_point() {
+        let points: Vec<_> = Line::new([3, 3], [3, 3]).iter().collect();
+
+        assert_eq!(vec![IVec2::new(3, 3)], points);
+    }
+
+    #[test]
+    fn diagonal() {
+        let points: Vec<_> = Line::new([0, 0], [3, 3]).iter().collect();
+
+        for (i, p) in points.iter().enumerate() {
+            assert_eq!(IVec2::splat(i as i32), *p);
+        }
+    }
+}
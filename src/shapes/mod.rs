@@ -1,4 +1,7 @@
+mod line;
 mod rect;
 
+pub use line::Line;
+pub use line::LineIterator;
 pub use rect::Rect;
 pub use rect::RectIterator;
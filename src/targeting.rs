@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+
+use crate::{
+    action::{Action, ActionEvent},
+    inventory::INVENTORY_INPUT_SYSTEM_LABEL,
+    map::Map,
+    map_state::MapActors,
+    monster::Monster,
+    movement::Position,
+    player::{read_movement, InputMode, Player},
+    replay::ReplayPlayback,
+    shapes::Line,
+    visibility::MapView,
+};
+
+/// Label for the system moving the targeting cursor. Occurs in [CoreStage::PreUpdate].
+pub const TARGETING_INPUT_SYSTEM_LABEL: &str = "targeting_input";
+
+/// Lets the player pick a target on the map for ranged items.
+///
+/// Move the cursor with the movement keys or cycle through visible monsters
+/// with `Tab`. `Enter` confirms the target and `Escape` cancels.
+pub struct TargetingPlugin;
+
+impl Plugin for TargetingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Targeting>()
+        .add_system_to_stage(CoreStage::PreUpdate, targeting_input
+            .label(TARGETING_INPUT_SYSTEM_LABEL)
+            // So confirming an item in the inventory doesn't also confirm the target
+            .before(INVENTORY_INPUT_SYSTEM_LABEL)
+        );
+    }
+}
+
+/// The state of [InputMode::Targeting].
+#[derive(Default, Debug)]
+pub struct Targeting {
+    pub cursor: IVec2,
+    pub range: u32,
+    /// The inventory slot of the item being aimed.
+    pub item: usize,
+}
+
+/// The path a projectile takes towards a target.
+pub struct LineOfFire {
+    /// Every tile the projectile passes through, not including the start.
+    pub path: Vec<IVec2>,
+    /// The first actor in the way, if any.
+    pub hit: Option<Entity>,
+}
+
+/// Trace a line from `from` towards `to`, stopping at the first opaque tile or actor.
+pub fn line_of_fire(from: IVec2, to: IVec2, map: &Map, actors: &MapActors) -> LineOfFire {
+    let mut path = Vec::new();
+    for p in Line::new(from, to).iter().skip(1) {
+        path.push(p);
+        if map.0[p].is_opaque() {
+            break;
+        }
+        if let Some(actor) = actors.0[p] {
+            return LineOfFire {
+                path,
+                hit: Some(actor),
+            };
+        }
+    }
+    LineOfFire { path, hit: None }
+}
+
+pub fn in_range(from: IVec2, to: IVec2, range: u32) -> bool {
+    (to - from).abs().max_element() <= range as i32
+}
+
+/// Positions of the monsters in view and in range, closest first.
+pub fn visible_targets<'a>(
+    from: IVec2,
+    view: &MapView,
+    range: u32,
+    monsters: impl Iterator<Item = &'a Position>,
+) -> Vec<IVec2> {
+    let mut targets: Vec<IVec2> = monsters
+        .map(|pos| pos.0)
+        .filter(|p| view.0[*p] && in_range(from, *p, range))
+        .collect();
+    targets.sort_by_key(|p| ((*p - from).abs().max_element(), p.x, p.y));
+    targets
+}
+
+fn targeting_input(
+    input: Res<Input<KeyCode>>,
+    mut mode: ResMut<InputMode>,
+    mut targeting: ResMut<Targeting>,
+    q_player: Query<(Entity, &Position, &MapView), With<Player>>,
+    q_monsters: Query<&Position, With<Monster>>,
+    replay: Option<Res<ReplayPlayback>>,
+    mut evt_action: EventWriter<ActionEvent>,
+) {
+    if replay.is_some() || *mode != InputMode::Targeting {
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Escape) {
+        *mode = InputMode::Game;
+        return;
+    }
+
+    let (player, pos, view) = match q_player.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    if input.just_pressed(KeyCode::Return) || input.just_pressed(KeyCode::T) {
+        evt_action.send(ActionEvent {
+            actor: player,
+            action: Action::UseItemAt(targeting.item, targeting.cursor.into()),
+        });
+        *mode = InputMode::Game;
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Tab) {
+        let targets = visible_targets(pos.0, view, targeting.range, q_monsters.iter());
+        if !targets.is_empty() {
+            let next = match targets.iter().position(|p| *p == targeting.cursor) {
+                Some(i) => (i + 1) % targets.len(),
+                None => 0,
+            };
+            targeting.cursor = targets[next];
+        }
+        return;
+    }
+
+    let dir = read_movement(&input);
+    if dir.cmpeq(IVec2::ZERO).all() {
+        return;
+    }
+
+    // The cursor can only move over tiles the player can see
+    let next = targeting.cursor + dir;
+    if view.0.in_bounds(next) && view.0[next] && in_range(pos.0, next, targeting.range) {
+        targeting.cursor = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sark_grids::Grid;
+
+    use crate::map::MapTile;
+
+    use super::*;
+
+    #[test]
+    fn line_of_fire_stops_at_walls() {
+        let mut map = Map(Grid::default([10, 10]));
+        for x in 0..10 {
+            map.0[[x, 5]] = MapTile::Floor;
+        }
+        let actors = MapActors(Grid::default([10, 10]));
+
+        let fire = line_of_fire(IVec2::new(1, 5), IVec2::new(8, 5), &map, &actors);
+        assert_eq!(7, fire.path.len());
+        assert!(fire.hit.is_none());
+
+        map.0[[4, 5]] = MapTile::Wall;
+        let fire = line_of_fire(IVec2::new(1, 5), IVec2::new(8, 5), &map, &actors);
+        assert_eq!(Some(&IVec2::new(4, 5)), fire.path.last());
+    }
+}
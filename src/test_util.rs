@@ -0,0 +1,49 @@
+//! Helpers for tests that run systems in an [App].
+
+use bevy::{ecs::event::Events, prelude::*};
+use sark_grids::Grid;
+
+use crate::{
+    map::Map,
+    map_state::{MapActors, MapObstacles},
+    movement::Position,
+    rng::DiceRng,
+    turn_system::{Actor, Energy, TakingATurn, TURN_ENERGY},
+    ui::PrintLog,
+};
+
+/// An app with the given map spawned, the map state to go with it and the
+/// resources most systems read. Tests add the plugins, systems and entities
+/// they need.
+pub fn map_app(map: Map) -> App {
+    let mut app = App::new();
+    app.insert_resource(MapObstacles::from_map(&map))
+    .insert_resource(MapActors(Grid::default(map.0.size())))
+    .insert_resource(DiceRng::seeded(0))
+    .init_resource::<PrintLog>();
+    app.world.spawn().insert(map);
+    app
+}
+
+/// Spawn an actor at the start of its turn at `pos` and add it to the map state.
+pub fn spawn_actor(app: &mut App, pos: IVec2, bundle: impl Bundle) -> Entity {
+    let actor = app.world.spawn()
+        .insert_bundle((Actor, TakingATurn, Position(pos), Energy(TURN_ENERGY)))
+        .insert_bundle(bundle)
+        .id();
+    app.world.resource_mut::<MapObstacles>().0[pos] = true;
+    app.world.resource_mut::<MapActors>().0[pos] = Some(actor);
+    actor
+}
+
+/// Send an event, then run the app once.
+pub fn send<E: Send + Sync + 'static>(app: &mut App, event: E) {
+    app.world.resource_mut::<Events<E>>().send(event);
+    app.update();
+}
+
+/// Every event of a type still in the app's event buffers.
+pub fn sent<E: Clone + Send + Sync + 'static>(app: &App) -> Vec<E> {
+    let events = app.world.resource::<Events<E>>();
+    events.get_reader().iter(events).cloned().collect()
+}
@@ -1,4 +1,9 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::player::InputMode;
 
 pub struct TurnSystemPlugin;
 
@@ -10,18 +15,51 @@ pub const TURN_END_SYSTEM_LABEL: &str = "turnend";
 impl Plugin for TurnSystemPlugin {
     fn build(&self, app: &mut App) {
         app
+        .init_resource::<TurnQueue>()
+        .add_system_to_stage(CoreStage::PreUpdate, schedule_new_actors.before(TURN_BEGIN_SYSTEM_LABEL))
         .add_system_to_stage(CoreStage::PreUpdate, turn_begin_system.label(TURN_BEGIN_SYSTEM_LABEL))
         .add_system_to_stage(CoreStage::PostUpdate, turn_end_system.label(TURN_END_SYSTEM_LABEL));
     }
 }
 
-/// When an actor's energy reaches or exceeds 100, it will be given a turn.
-#[derive(Default, Debug, Component)]
+/// The energy an actor has at the start of its turn, and the cost of most actions.
+pub const TURN_ENERGY: i32 = 100;
+
+/// Energy spent moving onto a floor tile, other tiles can cost more, see
+/// [crate::map::TileProperties::move_cost].
+pub const MOVE_COST: i32 = TURN_ENERGY;
+/// Energy spent attacking.
+pub const ATTACK_COST: i32 = TURN_ENERGY;
+/// Energy spent waiting a turn.
+pub const WAIT_COST: i32 = TURN_ENERGY;
+/// Energy spent opening a door.
+pub const OPEN_DOOR_COST: i32 = TURN_ENERGY;
+/// Energy spent taking the stairs.
+pub const STAIRS_COST: i32 = TURN_ENERGY;
+/// Energy spent picking up an item.
+pub const PICK_UP_COST: i32 = TURN_ENERGY / 2;
+/// Energy spent dropping an item.
+pub const DROP_COST: i32 = TURN_ENERGY / 2;
+/// Energy spent using an item.
+pub const USE_ITEM_COST: i32 = TURN_ENERGY;
+/// Energy spent equipping or unequipping an item.
+pub const EQUIP_COST: i32 = TURN_ENERGY;
+
+/// An actor's turn lasts until it has spent some of its energy. Whatever it
+/// spent is earned back at its [Speed] before its next turn.
+#[derive(Default, Debug, Clone, Component, Serialize, Deserialize)]
 pub struct Energy(pub i32);
 
+impl Energy {
+    /// Pay for an action, ending the turn.
+    pub fn spend(&mut self, cost: i32) {
+        self.0 -= cost;
+    }
+}
+
 /// Determines how frequently an actor gets to take their turn,
-/// relative to other actors.
-#[derive(Debug, Component)]
+/// relative to other actors. The energy an actor earns back each tick.
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct Speed(pub i32);
 
 /// A tag for actors that can perform actions and take turns.
@@ -32,35 +70,221 @@ pub struct Actor;
 #[derive(Debug, Component)]
 pub struct TakingATurn;
 
+/// The order actors were first scheduled in. Actors due at the same time act
+/// in this order. It's saved with the actor so ties play out the same way
+/// after loading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Component, Serialize, Deserialize)]
+pub struct TurnOrder(pub u64);
+
+/// How many ticks a loaded actor had left until its next turn. Used in
+/// place of its energy when it's first scheduled, then removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct NextTurnIn(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ScheduledTurn {
+    time: u64,
+    /// Breaks ties between actors due at the same time.
+    order: TurnOrder,
+    entity: Entity,
+}
+
+/// Every actor's next turn, earliest first.
+#[derive(Default)]
+pub struct TurnQueue {
+    /// The time of the turns being taken.
+    time: u64,
+    /// The [TurnOrder] given to the next new actor.
+    next_order: u64,
+    turns: BinaryHeap<Reverse<ScheduledTurn>>,
+    /// The actors taking a turn, in the order they should act.
+    acting: Vec<Entity>,
+}
+
+impl TurnQueue {
+    /// The actors taking a turn, in the order they should act.
+    pub fn acting(&self) -> &[Entity] {
+        &self.acting
+    }
+
+    /// Give an actor a turn once it has earned back the energy it's missing.
+    pub fn schedule(&mut self, entity: Entity, order: TurnOrder, energy: i32, speed: i32) {
+        self.schedule_in(entity, order, ticks_to_recover(energy, speed));
+    }
+
+    /// Give an actor a turn after the given number of ticks.
+    pub fn schedule_in(&mut self, entity: Entity, order: TurnOrder, ticks: u64) {
+        let turn = ScheduledTurn {
+            time: self.time + ticks,
+            order,
+            entity,
+        };
+        self.turns.push(Reverse(turn));
+    }
+
+    /// How many ticks until an actor's next turn. `None` if it's taking a
+    /// turn or isn't scheduled.
+    pub fn ticks_until(&self, entity: Entity) -> Option<u64> {
+        self.turns
+            .iter()
+            .find(|Reverse(turn)| turn.entity == entity)
+            .map(|Reverse(turn)| turn.time - self.time)
+    }
+
+    /// Take every turn due at the earliest time. Actors that no longer
+    /// exist are dropped.
+    fn pop_due(&mut self, exists: impl Fn(Entity) -> bool) -> &[Entity] {
+        self.acting.clear();
+        while let Some(Reverse(next)) = self.turns.peek().copied() {
+            if !self.acting.is_empty() && next.time != self.time {
+                break;
+            }
+            self.turns.pop();
+            if exists(next.entity) {
+                self.time = next.time;
+                self.acting.push(next.entity);
+            }
+        }
+        &self.acting
+    }
+}
+
+/// How many ticks it takes to get back to [TURN_ENERGY]. Actors without a
+/// positive speed still get a turn eventually.
+fn ticks_to_recover(energy: i32, speed: i32) -> u64 {
+    let missing = (TURN_ENERGY - energy).max(0) as u64;
+    let speed = speed.max(1) as u64;
+    ((missing + speed - 1) / speed).max(1)
+}
+
+/// Put actors new to the level, or loaded from a save, into the queue.
+///
+/// Loaded actors keep their [TurnOrder] and [NextTurnIn], actors that don't
+/// have an order yet are ordered after every other actor, in the order they
+/// were spawned.
+#[allow(clippy::type_complexity)]
+fn schedule_new_actors(
+    mut commands: Commands,
+    mut queue: ResMut<TurnQueue>,
+    q_new_actors: Query<
+        (Entity, &Energy, &Speed, Option<&TurnOrder>, Option<&NextTurnIn>, Option<&TakingATurn>),
+        Added<Actor>,
+    >,
+) {
+    let mut new_actors: Vec<_> = q_new_actors.iter().collect();
+    new_actors.sort_by_key(|(entity, _, _, order, ..)| (order.is_none(), order.copied(), *entity));
+
+    for (entity, energy, speed, order, next_turn_in, taking_turn) in new_actors {
+        let order = match order {
+            Some(order) => {
+                queue.next_order = queue.next_order.max(order.0 + 1);
+                *order
+            },
+            None => {
+                let order = TurnOrder(queue.next_order);
+                queue.next_order += 1;
+                commands.entity(entity).insert(order);
+                order
+            },
+        };
+
+        // Saved in the middle of their turn
+        if taking_turn.is_some() {
+            queue.acting.push(entity);
+        } else if let Some(next_turn_in) = next_turn_in {
+            queue.schedule_in(entity, order, next_turn_in.0);
+            commands.entity(entity).remove::<NextTurnIn>();
+        } else {
+            queue.schedule(entity, order, energy.0, speed.0);
+        }
+    }
+}
+
 fn turn_begin_system(
     mut commands: Commands,
-    mut q_waiting_actors: Query<(Entity, &mut Energy, &Speed), (With<Actor>, Without<TakingATurn>)>,
-    q_acting_actors: Query<&Actor, (With<Energy>, With<Speed>, With<TakingATurn>)>,
+    mut queue: ResMut<TurnQueue>,
+    mut q_energy: Query<&mut Energy, With<Actor>>,
+    q_acting_actors: Query<&Actor, With<TakingATurn>>,
+    mode: Res<InputMode>,
 ) {
-    if !q_acting_actors.is_empty() {
+    if !q_acting_actors.is_empty() || *mode == InputMode::Visualizer {
         return;
     }
 
-    let mut done = false;
-    while !done {
-        for (entity, mut energy, speed) in q_waiting_actors.iter_mut() {
-            energy.0 += speed.0;
-
-            if energy.0 >= 100 {
-                done = true;
-                commands.entity(entity).insert(TakingATurn);
-            }
+    let acting = queue.pop_due(|entity| q_energy.get(entity).is_ok()).to_vec();
+    for entity in acting {
+        if let Ok(mut energy) = q_energy.get_mut(entity) {
+            energy.0 = TURN_ENERGY;
         }
+        commands.entity(entity).insert(TakingATurn);
     }
 }
 
 fn turn_end_system(
-    mut commands: Commands, 
-    q_actors: Query<(Entity, &Energy), (With<Actor>, With<TakingATurn>)>,
+    mut commands: Commands,
+    mut queue: ResMut<TurnQueue>,
+    q_actors: Query<(Entity, &Energy, &Speed, &TurnOrder), (With<Actor>, With<TakingATurn>)>,
 ) {
-    for (entity, energy) in q_actors.iter() {
-        if energy.0 < 100 {
+    for (entity, energy, speed, order) in q_actors.iter() {
+        if energy.0 < TURN_ENERGY {
             commands.entity(entity).remove::<TakingATurn>();
+            queue.schedule(entity, *order, energy.0, speed.0);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ties_go_in_turn_order() {
+        let mut queue = TurnQueue::default();
+        let entities: Vec<Entity> = (0..4).map(Entity::from_raw).collect();
+        // Scheduled in reverse, but act in turn order
+        for (i, e) in entities.iter().enumerate().rev() {
+            queue.schedule(*e, TurnOrder(i as u64), 0, 10);
+        }
+
+        let acting = queue.pop_due(|_| true).to_vec();
+        assert_eq!(entities, acting);
+        assert_eq!(10, queue.time);
+    }
+
+    #[test]
+    fn faster_actors_act_first() {
+        let mut queue = TurnQueue::default();
+        let (slow, fast) = (Entity::from_raw(0), Entity::from_raw(1));
+        queue.schedule(slow, TurnOrder(0), 0, 10);
+        queue.schedule(fast, TurnOrder(1), 0, 25);
+
+        assert_eq!(&[fast], queue.pop_due(|_| true));
+        // A cheap action gets the next turn sooner
+        queue.schedule(fast, TurnOrder(1), TURN_ENERGY - PICK_UP_COST, 25);
+        assert_eq!(&[fast], queue.pop_due(|_| true));
+        assert_eq!(&[slow], queue.pop_due(|_| true));
+    }
+
+    #[test]
+    fn ticks_until_counts_from_now() {
+        let mut queue = TurnQueue::default();
+        let (first, second) = (Entity::from_raw(0), Entity::from_raw(1));
+        queue.schedule(first, TurnOrder(0), 0, 25);
+        queue.schedule_in(second, TurnOrder(1), 7);
+
+        assert_eq!(&[first], queue.pop_due(|_| true));
+        assert_eq!(None, queue.ticks_until(first));
+        assert_eq!(Some(3), queue.ticks_until(second));
+    }
+
+    #[test]
+    fn removed_actors_are_skipped() {
+        let mut queue = TurnQueue::default();
+        let (gone, stays) = (Entity::from_raw(0), Entity::from_raw(1));
+        queue.schedule(gone, TurnOrder(0), 0, 10);
+        queue.schedule(stays, TurnOrder(1), 0, 0);
+
+        assert_eq!(&[stays], queue.pop_due(|e| e != gone));
+        assert!(queue.pop_due(|_| true).is_empty());
+    }
+}
@@ -1,8 +1,13 @@
 use bevy::prelude::*;
 use bevy_ascii_terminal::{*, ui::*};
 use interpolation::Lerp;
+use serde::{Deserialize, Serialize};
 
-use crate::{UI_SIZE, VIEWPORT_SIZE, events::AttackEvent, combat::{HitPoints, MaxHitPoints}, player::Player};
+use crate::{UI_SIZE, VIEWPORT_SIZE, events::AttackEvent, combat::{HitPoints, MaxHitPoints, Defense, Strength, AttackDice}, player::{Player, InputMode}, dungeon::Dungeon, inventory::{Inventory, InventoryCursor}, equipment::Equipment};
+
+/// Number of inventory slots shown in each column of the inventory screen.
+pub const INVENTORY_ROWS: usize = UI_SIZE[1] as usize - 2;
+const INVENTORY_COLUMN_WIDTH: usize = 26;
 
 pub struct UiPlugin;
 
@@ -10,7 +15,7 @@ pub struct UiPlugin;
 pub struct UiTerminal;
 
 /// Log for terminal messages to be displayed to the user.
-#[derive(Default)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct PrintLog {
     /// History of logged messages
     log: Vec<String>,
@@ -55,12 +60,20 @@ fn handle_attacks(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_print(
     mut print_log: ResMut<PrintLog>,
     mut q_term: Query<&mut Terminal, With<UiTerminal>>,
-    q_player: Query<(&HitPoints, &MaxHitPoints), With<Player>>,
+    q_player: Query<(&HitPoints, &MaxHitPoints, &Inventory, &Equipment), With<Player>>,
+    q_stats: Query<(&Defense, &Strength, &AttackDice), With<Player>>,
+    q_player_changed: Query<(), (With<Player>, Or<(Changed<Inventory>, Changed<Equipment>, Changed<Defense>, Changed<Strength>, Changed<AttackDice>)>)>,
+    q_names: Query<&Name>,
+    dungeon: Res<Dungeon>,
+    mode: Res<InputMode>,
+    cursor: Res<InventoryCursor>,
 ) {
-    if print_log.is_changed() {
+    let player_changed = q_player_changed.iter().next().is_some();
+    if print_log.is_changed() || dungeon.is_changed() || mode.is_changed() || cursor.is_changed() || player_changed {
         let len = print_log.log.len();
         if len > 6 {
             print_log.log.drain(0..len - 6);
@@ -74,15 +87,34 @@ fn handle_print(
              └─┘"
         );
         term.draw_border(border);
-        for (i,text) in print_log.log.iter().rev().enumerate().take(6) {
-            let (t, min,max) = (i as f32 / 6.0, 0.15, 1.0);
-            let alpha = f32::lerp(&min, &max, &t);
-            let y = term.side_index(Side::Top) as i32 - 1 - i as i32;
-            let fg_color = Color::rgba(1.0, 1.0, 1.0, 1.0 - alpha);
-            term.put_string([1,y], text.fg(fg_color));
+
+        match *mode {
+            InputMode::Inventory => {
+                if let Ok((_, _, inventory, equipment)) = q_player.get_single() {
+                    draw_inventory(&mut term, inventory, equipment, &q_names, cursor.0);
+                }
+            },
+            _ => {
+                for (i,text) in print_log.log.iter().rev().enumerate().take(6) {
+                    let (t, min,max) = (i as f32 / 6.0, 0.15, 1.0);
+                    let alpha = f32::lerp(&min, &max, &t);
+                    let y = term.side_index(Side::Top) as i32 - 1 - i as i32;
+                    let fg_color = Color::rgba(1.0, 1.0, 1.0, 1.0 - alpha);
+                    term.put_string([1,y], text.fg(fg_color));
+                }
+            },
+        }
+
+        let mut stats_string = format!("Depth {}", dungeon.depth);
+        if let Ok((defense, strength, dice)) = q_stats.get_single() {
+            // Show strength as part of the attack roll
+            let mut attack = dice.clone();
+            attack.0.bonus += strength.0;
+            stats_string += &format!(" | Atk {} | Def {}", attack, defense.0);
         }
+        term.put_string([2, 0], stats_string.as_str().fg(Color::YELLOW));
 
-        if let Ok((hp, max)) = q_player.get_single() {
+        if let Ok((hp, max, _, _)) = q_player.get_single() {
             let hp_string = format!("HP: {} / {}", hp.0.to_string(), max.0.to_string());
             let y = term.side_index(Side::Top) as i32;
             let bar_width = term.width() as i32 - 20;
@@ -102,3 +134,29 @@ fn handle_print(
     }
 }
 
+/// Draw the inventory in columns of [INVENTORY_ROWS] slots, highlighting the selected slot.
+fn draw_inventory(term: &mut Terminal, inventory: &Inventory, equipment: &Equipment, q_names: &Query<&Name>, cursor: usize) {
+    let top = term.side_index(Side::Top) as i32 - 1;
+
+    if inventory.items.is_empty() {
+        term.put_string([1, top], "Your inventory is empty.".fg(Color::GRAY));
+    }
+
+    for (i, item) in inventory.items.iter().enumerate() {
+        let name = q_names.get(*item).map_or("???", |name| name.as_str());
+        let mut text = format!("{}) {}", (b'a' + i as u8) as char, name);
+        if let Some(slot) = equipment.slot_of(*item) {
+            text += &format!(" ({})", slot);
+        }
+        let text: String = text.chars().take(INVENTORY_COLUMN_WIDTH - 1).collect();
+
+        let x = 1 + (i / INVENTORY_ROWS * INVENTORY_COLUMN_WIDTH) as i32;
+        let y = top - (i % INVENTORY_ROWS) as i32;
+        let fg_color = if i == cursor { Color::YELLOW } else { Color::WHITE };
+        term.put_string([x, y], text.as_str().fg(fg_color));
+    }
+
+    let title = format!("Items {}/{} [U]se [E]quip [D]rop [I]", inventory.items.len(), inventory.capacity);
+    let x = term.width() as i32 - title.len() as i32 - 2;
+    term.put_string([x, 0], title.as_str().fg(Color::YELLOW));
+}
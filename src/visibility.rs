@@ -1,8 +1,9 @@
 use bevy::{math::IVec2, prelude::*};
 use sark_grids::Grid;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    map::{Map, MapTile},
+    map::Map,
     movement::Position,
 };
 
@@ -19,13 +20,13 @@ impl Plugin for VisiblityPlugin {
     }
 }
 
-#[derive(Component, Debug, Default)]
+#[derive(Component, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct MapMemory(pub Vec<bool>);
 
 #[derive(Component, Debug, Default)]
 pub struct MapView(pub Grid<bool>);
 
-#[derive(Component, Debug, Default)]
+#[derive(Component, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ViewRange(pub u32);
 
 pub struct VisibilityMap<'a> {
@@ -39,7 +40,7 @@ impl<'a> adam_fov_rs::VisibilityMap for VisibilityMap<'a> {
         if !self.map.0.in_bounds(p) {
             return true;
         }
-        self.map.0[p] == MapTile::Wall
+        self.map.0[p].is_opaque()
     }
 
     fn is_in_bounds(&self, p: impl GridPoint) -> bool {
@@ -64,13 +65,17 @@ impl<'a> adam_fov_rs::VisibilityMap for VisibilityMap<'a> {
 #[allow(clippy::type_complexity)]
 fn view_system(
     mut q_view: Query<
-        (&mut MapView, &Position, &ViewRange),
-        (Changed<Position>, Without<MapMemory>),
+        (&mut MapView, &Position, &ViewRange, ChangeTrackers<Position>),
+        Without<MapMemory>,
     >,
-    q_map: Query<&Map>,
+    q_map: Query<(&Map, ChangeTrackers<Map>)>,
 ) {
-    if let Ok(map) = q_map.get_single() {
-        for (mut view, pos, range) in q_view.iter_mut() {
+    if let Ok((map, map_changes)) = q_map.get_single() {
+        for (mut view, pos, range, moved) in q_view.iter_mut() {
+            // Opening a door changes what can be seen without anyone moving
+            if !moved.is_changed() && !map_changes.is_changed() {
+                continue;
+            }
             //println!("Updating mapview");
             let view_vec = &mut view.0;
 
@@ -93,12 +98,16 @@ fn view_system(
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn view_memory_system(
-    mut q_view: Query<(&mut MapView, &mut MapMemory, &Position, &ViewRange), Changed<Position>>,
-    q_map: Query<&Map>,
+    mut q_view: Query<(&mut MapView, &mut MapMemory, &Position, &ViewRange, ChangeTrackers<Position>)>,
+    q_map: Query<(&Map, ChangeTrackers<Map>)>,
 ) {
-    if let Ok(map) = q_map.get_single() {
-        for (mut view, mut memory, pos, range) in q_view.iter_mut() {
+    if let Ok((map, map_changes)) = q_map.get_single() {
+        for (mut view, mut memory, pos, range, moved) in q_view.iter_mut() {
+            if !moved.is_changed() && !map_changes.is_changed() {
+                continue;
+            }
             //println!("Updating mapview");
             let view_vec = &mut view.0;
 
@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+use bevy_ascii_terminal::{*, ui::BorderGlyphs};
+
+use crate::{
+    camera::MapViewport,
+    config,
+    inventory::ItemTemplates,
+    map_gen::MapSnapshot,
+    monster::MonsterTemplates,
+    player::InputMode,
+    render::RENDER_SYSTEM_LABEL,
+    save::QUIT_INPUT_SYSTEM_LABEL,
+    targeting::TARGETING_INPUT_SYSTEM_LABEL,
+    GameTerminal,
+};
+
+/// Label for the system stepping through map generation snapshots. Occurs in [CoreStage::PreUpdate].
+pub const VISUALIZER_INPUT_SYSTEM_LABEL: &str = "visualizer_input";
+
+/// Seconds between snapshots while playing.
+const SNAPSHOT_INTERVAL: f32 = 0.08;
+
+/// Plays back how each level was generated, one snapshot at a time, when
+/// run with `--visualize`.
+///
+/// `Space` pauses, the left and right arrows step through the snapshots
+/// and `Enter` skips to the game.
+pub struct VisualizerPlugin;
+
+impl Plugin for VisualizerPlugin {
+    fn build(&self, app: &mut App) {
+        if !config::has_arg("--visualize") {
+            return;
+        }
+
+        app.insert_resource(MapGenVisualizer)
+        .add_system_to_stage(CoreStage::PreUpdate, visualizer_input
+            .label(VISUALIZER_INPUT_SYSTEM_LABEL)
+            // Runs ahead of the game's input so the `Enter` that skips to the
+            // game can be cleared before the game sees it
+            .after(QUIT_INPUT_SYSTEM_LABEL)
+            .before(TARGETING_INPUT_SYSTEM_LABEL)
+        )
+        .add_system_to_stage(CoreStage::Last, render_snapshot.after(RENDER_SYSTEM_LABEL));
+    }
+}
+
+/// Tells the map generator to record its history, see [crate::map_gen::MapHistory].
+pub struct MapGenVisualizer;
+
+/// The snapshots of the level being played back.
+pub struct MapGenPlayback {
+    pub snapshots: Vec<MapSnapshot>,
+    pub index: usize,
+    pub paused: bool,
+    timer: Timer,
+}
+
+impl MapGenPlayback {
+    pub fn new(snapshots: Vec<MapSnapshot>) -> Self {
+        Self {
+            snapshots,
+            index: 0,
+            paused: false,
+            timer: Timer::from_seconds(SNAPSHOT_INTERVAL, true),
+        }
+    }
+
+    fn last(&self) -> usize {
+        self.snapshots.len().saturating_sub(1)
+    }
+}
+
+fn visualizer_input(
+    mut commands: Commands,
+    mut input: ResMut<Input<KeyCode>>,
+    time: Res<Time>,
+    mut mode: ResMut<InputMode>,
+    playback: Option<ResMut<MapGenPlayback>>,
+) {
+    let mut playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+
+    // The game is paused until the playback is done
+    if *mode != InputMode::Visualizer {
+        *mode = InputMode::Visualizer;
+    }
+
+    if input.just_pressed(KeyCode::Return) || playback.snapshots.is_empty() {
+        input.clear_just_pressed(KeyCode::Return);
+        commands.remove_resource::<MapGenPlayback>();
+        *mode = InputMode::Game;
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if input.just_pressed(KeyCode::Right) {
+        playback.paused = true;
+        playback.index = (playback.index + 1).min(playback.last());
+    }
+    if input.just_pressed(KeyCode::Left) {
+        playback.paused = true;
+        playback.index = playback.index.saturating_sub(1);
+    }
+
+    if !playback.paused && playback.index < playback.last() && playback.timer.tick(time.delta()).just_finished() {
+        playback.index += 1;
+    }
+}
+
+fn render_snapshot(
+    playback: Option<Res<MapGenPlayback>>,
+    monsters: Res<MonsterTemplates>,
+    items: Res<ItemTemplates>,
+    viewport: Res<MapViewport>,
+    mut q_render_terminal: Query<&mut Terminal, With<GameTerminal>>,
+) {
+    // Drawn every frame so the game's own rendering can't show through
+    let playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+    let snapshot = match playback.snapshots.get(playback.index) {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+    let mut term = match q_render_terminal.get_single_mut() {
+        Ok(term) => term,
+        Err(_) => return,
+    };
+
+    // Show the middle of maps too big for the screen
+    let mut view = *viewport;
+    let size = snapshot.tiles.size().as_ivec2();
+    view.origin = view.focus(size / 2, size);
+
+    term.clear();
+
+    for (i, tile) in snapshot.tiles.iter().enumerate() {
+        view.put_tile(&mut term, snapshot.tiles.index_to_pos(i), Tile::from(*tile));
+    }
+    for spawn in snapshot.items.iter() {
+        if let Some(item) = items.get(&spawn.name) {
+            view.put_tile(&mut term, spawn.position, Tile::from(&item.bundle().renderable));
+        }
+    }
+    for spawn in snapshot.monsters.iter() {
+        if let Some(monster) = monsters.get(&spawn.name) {
+            view.put_tile(&mut term, spawn.position, Tile::from(&monster.bundle().movable.renderable));
+        }
+    }
+
+    term.draw_border(BorderGlyphs::single_line());
+
+    let state = if playback.paused { "Paused" } else { "Playing" };
+    let text = format!(
+        "{} {}/{} {} - [Space] Pause [Left/Right] Step [Enter] Play",
+        snapshot.label,
+        playback.index + 1,
+        playback.snapshots.len(),
+        state,
+    );
+    let y = term.side_index(Side::Top) as i32;
+    term.put_string([2, y], text.as_str().fg(Color::YELLOW));
+}